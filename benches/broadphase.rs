@@ -0,0 +1,224 @@
+// Compares checking every bullet against every block with going through the spatial
+// hash first, then times the full `detect_bullet_collisions` pass the game runs each
+// frame. Run with `cargo bench --bench broadphase`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bevy::{ecs::event::Events, prelude::*};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use b::block::Block;
+use b::broadphase::{
+    update_block_broadphase, update_body_broadphase, BlockBroadphase, BodyBroadphase, SpatialHash,
+    BROADPHASE_CELL_SIZE,
+};
+use b::bullets::{Bullet, Velocity};
+use b::colliders::colliders_o::{
+    circle_polygon_push, detect_bullet_collisions, BlockCollider, CircleCollider, ColliderType,
+    CollisionStarted,
+};
+use b::player::PLAYER_RADIUS;
+
+const BULLETS: usize = 1000;
+const BLOCKS: usize = 500;
+const FRAMES: u32 = 200;
+const BULLET_RADIUS: f32 = 5.0;
+const HALF_EXTENT: f32 = 5000.0;
+const PLAYERS: usize = 16;
+// How far a bullet moves in one 60 Hz frame.
+const BULLET_STEP: f32 = 60.0;
+
+fn random_blocks(rng: &mut StdRng) -> Vec<BlockCollider> {
+    (0..BLOCKS)
+        .map(|_| {
+            let x = rng.gen_range(-HALF_EXTENT..HALF_EXTENT);
+            let y = rng.gen_range(-HALF_EXTENT..HALF_EXTENT);
+            let w = rng.gen_range(50.0..400.0);
+            let h = rng.gen_range(50.0..400.0);
+            let block = Block::from_points(
+                &[(x, y), (x + w, y), (x + w, y + h), (x, y + h)],
+                rng.gen_range(0.0..std::f32::consts::TAU),
+            );
+            BlockCollider::new(ColliderType::Block, block.points)
+        })
+        .collect()
+}
+
+fn naive(bullets: &[Vec2], blocks: &[BlockCollider]) -> usize {
+    let mut hits = 0;
+    for &bullet in bullets {
+        for block in blocks {
+            if block.near(bullet, BULLET_RADIUS)
+                && circle_polygon_push(bullet, BULLET_RADIUS, &block.points).is_some()
+            {
+                hits += 1;
+                break;
+            }
+        }
+    }
+    hits
+}
+
+fn with_broadphase(
+    bullets: &[Vec2],
+    blocks: &[BlockCollider],
+    grid: &SpatialHash,
+    candidates: &mut Vec<Entity>,
+) -> usize {
+    let mut hits = 0;
+    for &bullet in bullets {
+        grid.query_circle(bullet, BULLET_RADIUS, candidates);
+        for &entity in candidates.iter() {
+            let block = &blocks[entity.index() as usize];
+            if block.near(bullet, BULLET_RADIUS)
+                && circle_polygon_push(bullet, BULLET_RADIUS, &block.points).is_some()
+            {
+                hits += 1;
+                break;
+            }
+        }
+    }
+    hits
+}
+
+fn random_position(rng: &mut StdRng) -> Vec2 {
+    Vec2::new(
+        rng.gen_range(-HALF_EXTENT..HALF_EXTENT),
+        rng.gen_range(-HALF_EXTENT..HALF_EXTENT),
+    )
+}
+
+// A world holding the blocks, some players and the bullets.
+fn collision_world(rng: &mut StdRng, blocks: &[BlockCollider]) -> World {
+    let mut world = World::new();
+    world.init_resource::<BlockBroadphase>();
+    world.init_resource::<BodyBroadphase>();
+    world.init_resource::<Events<CollisionStarted>>();
+    for block in blocks {
+        world.spawn(BlockCollider::new(
+            ColliderType::Block,
+            block.points.clone(),
+        ));
+    }
+    for _ in 0..PLAYERS {
+        let position = random_position(rng);
+        world.spawn((
+            Transform::from_xyz(position.x, position.y, 0.),
+            CircleCollider {
+                collider_type: ColliderType::Enemy,
+                radius: PLAYER_RADIUS,
+            },
+        ));
+    }
+    for _ in 0..BULLETS {
+        let position = random_position(rng);
+        let velocity = Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU)) * BULLET_STEP;
+        world.spawn((
+            Bullet {
+                velocity: Velocity {
+                    x: velocity.x,
+                    y: velocity.y,
+                },
+                position: position.into(),
+                previous: position.into(),
+                damage: 100,
+                falloff_start: 1000.,
+                range: 2000.,
+                travelled: 0.,
+                ricochets_left: 0,
+                penetration: 0.,
+                knockback: 0.,
+                stun_secs: 0.,
+            },
+            CircleCollider {
+                collider_type: ColliderType::Bullet,
+                radius: BULLET_RADIUS,
+            },
+        ));
+    }
+    world
+}
+
+// Steps every bullet, wrapping it back into the arena, then sweeps them all.
+fn detect_frame(world: &mut World, detect: &mut Schedule) -> usize {
+    let mut bullets = world.query::<&mut Bullet>();
+    for mut bullet in bullets.iter_mut(world) {
+        let mut position = Vec2::from(bullet.position);
+        if position.abs().max_element() > HALF_EXTENT {
+            position = -position;
+        }
+        bullet.previous = position.into();
+        bullet.position = (position + Vec2::new(bullet.velocity.x, bullet.velocity.y)).into();
+    }
+    detect.run(world);
+    world
+        .resource_mut::<Events<CollisionStarted>>()
+        .drain()
+        .count()
+}
+
+fn time(mut frame: impl FnMut(u32) -> usize) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut hits = 0;
+    for i in 0..FRAMES {
+        hits += black_box(frame(i));
+    }
+    (start.elapsed() / FRAMES, hits)
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(35);
+    let blocks = random_blocks(&mut rng);
+    // Bullets move between frames, so each frame gets its own set.
+    let frames: Vec<Vec<Vec2>> = (0..FRAMES)
+        .map(|_| (0..BULLETS).map(|_| random_position(&mut rng)).collect())
+        .collect();
+
+    let build_start = Instant::now();
+    let mut grid = SpatialHash::new(BROADPHASE_CELL_SIZE);
+    for (i, block) in blocks.iter().enumerate() {
+        grid.insert(Entity::from_raw(i as u32), block.min, block.max);
+    }
+    let build = build_start.elapsed();
+
+    let (naive_time, naive_hits) = time(|i| naive(&frames[i as usize], &blocks));
+    let mut candidates = Vec::new();
+    let (grid_time, grid_hits) =
+        time(|i| with_broadphase(&frames[i as usize], &blocks, &grid, &mut candidates));
+    assert_eq!(naive_hits, grid_hits, "broadphase missed collisions");
+
+    println!(
+        "{} bullets against {} blocks, {} frames",
+        BULLETS, BLOCKS, FRAMES
+    );
+    println!("naive:      {:>10.3?} per frame", naive_time);
+    println!(
+        "broadphase: {:>10.3?} per frame (grid built in {:.3?})",
+        grid_time, build
+    );
+    println!(
+        "speedup:    {:.1}x ({} hits in both)",
+        naive_time.as_secs_f64() / grid_time.as_secs_f64(),
+        grid_hits
+    );
+
+    let mut world = collision_world(&mut rng, &blocks);
+    let mut detect = Schedule::default();
+    // Both hashes are rebuilt every frame, like the game does. The first run is left out
+    // of the timing, since it's the one that allocates.
+    detect.add_systems(
+        (
+            update_block_broadphase,
+            update_body_broadphase,
+            detect_bullet_collisions,
+        )
+            .chain(),
+    );
+    detect.run(&mut world);
+    world.resource_mut::<Events<CollisionStarted>>().clear();
+    let (detect_time, collisions) = time(|_| detect_frame(&mut world, &mut detect));
+    println!(
+        "detect_bullet_collisions: {:>10.3?} per frame ({} bullets, {} players, {} collisions)",
+        detect_time, BULLETS, PLAYERS, collisions
+    );
+}
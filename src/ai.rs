@@ -0,0 +1,558 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use rand::Rng;
+
+use crate::{
+    audio::audioO::BulletSoundtrackPlayer,
+    broadphase::{update_body_broadphase, BlockBroadphase},
+    bullets::{
+        bullet_impacts, spawn_bullet, Bullet, FiredBy, FriendlyFire, Gunfire, Velocity, AI_OWNER,
+    },
+    colliders::colliders_o::{
+        detect_block_and_circle_collision, detect_bullet_collisions, segment_hits_block,
+        BlockCollider, CollisionStarted,
+    },
+    editor::GameMode,
+    enemy::{EnemyBarrel, EnemyPlayer},
+    health::Health,
+    map::{CurrentMap, MapData, MapState},
+    movement::{HitStun, Momentum, STUN_SLOW},
+    navigation::NavGrid,
+    player::Player,
+    weapons::WeaponKind,
+};
+
+// What the AI shoots with.
+const AI_WEAPON: WeaponKind = WeaponKind::Pistol;
+// How far away a block can be and still be considered for cover.
+const COVER_SEARCH_RADIUS: f32 = 2000.;
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg.to_lowercase().as_str() {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    fn enemy_count(self) -> usize {
+        match self {
+            Difficulty::Easy => 2,
+            Difficulty::Normal => 3,
+            Difficulty::Hard => 4,
+        }
+    }
+
+    fn speed(self) -> f32 {
+        match self {
+            Difficulty::Easy => 450.,
+            Difficulty::Normal => 600.,
+            Difficulty::Hard => 750.,
+        }
+    }
+
+    fn sight_range(self) -> f32 {
+        match self {
+            Difficulty::Easy => 1400.,
+            Difficulty::Normal => 1800.,
+            Difficulty::Hard => 2400.,
+        }
+    }
+
+    // Seconds between first seeing the player and opening fire.
+    fn reaction_time(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.9,
+            Difficulty::Normal => 0.5,
+            Difficulty::Hard => 0.25,
+        }
+    }
+
+    fn fire_interval(self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.2,
+            Difficulty::Normal => 0.8,
+            Difficulty::Hard => 0.5,
+        }
+    }
+
+    // Maximum random aim error in radians.
+    fn aim_error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.2,
+            Difficulty::Normal => 0.1,
+            Difficulty::Hard => 0.03,
+        }
+    }
+
+    // How much of the player's velocity is taken into account when leading shots.
+    fn lead_accuracy(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.0,
+            Difficulty::Normal => 0.6,
+            Difficulty::Hard => 1.0,
+        }
+    }
+
+    fn retreat_health(self) -> i32 {
+        match self {
+            Difficulty::Easy => 0,
+            Difficulty::Normal => 400,
+            Difficulty::Hard => 600,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AiState {
+    Patrol,
+    Chase,
+    TakeCover,
+}
+
+#[derive(Component)]
+pub struct AiController {
+    pub state: AiState,
+    patrol: Vec<Vec2>,
+    patrol_index: usize,
+    last_seen: Option<Vec2>,
+    last_target_pos: Option<Vec2>,
+    target_velocity: Vec2,
+    reaction: f32,
+    fire_cooldown: f32,
+    cover: Option<Vec2>,
+    cover_time: f32,
+    peek_time: f32,
+    path: Vec<Vec2>,
+    path_goal: Option<Vec2>,
+    repath: f32,
+}
+
+impl AiController {
+    pub fn new(spawn: Vec2) -> Self {
+        let patrol = vec![
+            spawn + Vec2::new(-600., -600.),
+            spawn + Vec2::new(600., -600.),
+            spawn + Vec2::new(600., 600.),
+            spawn + Vec2::new(-600., 600.),
+        ];
+
+        AiController {
+            state: AiState::Patrol,
+            patrol,
+            patrol_index: 0,
+            last_seen: None,
+            last_target_pos: None,
+            target_velocity: Vec2::ZERO,
+            reaction: 0.,
+            fire_cooldown: 0.,
+            cover: None,
+            cover_time: 0.,
+            peek_time: 0.,
+            path: Vec::new(),
+            path_goal: None,
+            repath: 0.,
+        }
+    }
+
+    // Next point to walk towards on the way to `target`, re-planning when the target
+    // moves or the current plan gets old.
+    fn steer(&mut self, position: Vec2, target: Vec2, nav_grid: &NavGrid, dt: f32) -> Vec2 {
+        self.repath -= dt;
+        let goal_moved = self
+            .path_goal
+            .is_none_or(|goal| goal.distance(target) > 100.);
+        if goal_moved || self.repath <= 0. {
+            self.path = nav_grid.find_path(position, target).unwrap_or_default();
+            self.path_goal = Some(target);
+            self.repath = 0.5;
+        }
+
+        while self
+            .path
+            .first()
+            .is_some_and(|next| position.distance(*next) < 30.)
+        {
+            self.path.remove(0);
+        }
+
+        self.path.first().copied().unwrap_or(position)
+    }
+
+    fn arrived(&self, position: Vec2, target: Vec2) -> bool {
+        position.distance(target) < 50. || (self.path.is_empty() && self.path_goal == Some(target))
+    }
+}
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(MapState::Ready), spawn_ai_enemies)
+            .add_systems(
+                Update,
+                (
+                    ai_think,
+                    // Walks like everyone else does, before bodies are hashed and pushed
+                    // out of each other and the walls.
+                    ai_move
+                        .before(update_body_broadphase)
+                        .before(detect_block_and_circle_collision),
+                    ai_shoot,
+                    // Has to see the bullet before `bullet_impacts` despawns it.
+                    ai_bullet_hits
+                        .after(detect_bullet_collisions)
+                        .before(bullet_impacts),
+                )
+                    .chain()
+                    .run_if(in_state(MapState::Ready).and(in_state(GameMode::Play))),
+            );
+    }
+}
+
+fn spawn_ai_enemies(
+    difficulty: Res<Difficulty>,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<MapData>>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    // The first spawn point belongs to the player.
+    let spawns: Vec<(f32, f32)> = maps
+        .get(&current_map.handle)
+        .map(|map| map.spawn_points.iter().skip(1).copied().collect())
+        .filter(|spawns: &Vec<(f32, f32)>| !spawns.is_empty())
+        .unwrap_or_else(|| vec![(1000., 0.)]);
+
+    for &spawn in spawns.iter().cycle().take(difficulty.enemy_count()) {
+        let enemy = EnemyPlayer::spawn(&mut commands, &mut meshes, &mut materials, spawn);
+        commands
+            .entity(enemy)
+            .insert(AiController::new(Vec2::new(spawn.0, spawn.1)));
+    }
+}
+
+// The walls the AI looks and hides behind, found through the broadphase.
+#[derive(SystemParam)]
+pub struct Walls<'w, 's> {
+    broadphase: Res<'w, BlockBroadphase>,
+    blocks: Query<'w, 's, &'static BlockCollider>,
+}
+
+impl Walls<'_, '_> {
+    fn has_line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let mut candidates = Vec::new();
+        self.broadphase
+            .query(from.min(to), from.max(to), &mut candidates);
+        !candidates.iter().any(|&entity| {
+            self.blocks
+                .get(entity)
+                .is_ok_and(|block| segment_hits_block(from, to, block))
+        })
+    }
+
+    // Picks a spot on the far side of the nearest block, as seen from the threat.
+    fn find_cover(&self, position: Vec2, threat: Vec2) -> Option<Vec2> {
+        let mut candidates = Vec::new();
+        self.broadphase
+            .query_circle(position, COVER_SEARCH_RADIUS, &mut candidates);
+        candidates
+            .iter()
+            .filter_map(|&entity| {
+                let block = self.blocks.get(entity).ok()?;
+                let center = (block.min + block.max) / 2.;
+                let half_extent = (block.max - block.min).length() / 2.;
+                let away = (center - threat).normalize_or_zero();
+                let spot = center + away * (half_extent.min(600.) + 120.);
+
+                if self.has_line_of_sight(threat, spot) {
+                    None
+                } else {
+                    Some(spot)
+                }
+            })
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)))
+    }
+}
+
+pub fn ai_think(
+    time: Res<Time>,
+    difficulty: Res<Difficulty>,
+    player_q: Query<&Transform, With<Player>>,
+    mut ai_q: Query<(&Transform, &mut AiController, &Health), Without<Player>>,
+    walls: Walls,
+) {
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+    let player_pos = player_transform.translation.truncate();
+    let dt = time.delta_secs();
+
+    for (transform, mut ai, health) in ai_q.iter_mut() {
+        let position = transform.translation.truncate();
+
+        if let Some(last) = ai.last_target_pos {
+            if dt > 0. {
+                let measured = (player_pos - last) / dt;
+                ai.target_velocity = ai.target_velocity.lerp(measured, 0.2);
+            }
+        }
+        ai.last_target_pos = Some(player_pos);
+
+        let visible = position.distance(player_pos) < difficulty.sight_range()
+            && walls.has_line_of_sight(position, player_pos);
+
+        if visible {
+            ai.last_seen = Some(player_pos);
+        }
+
+        ai.reaction -= dt;
+        ai.fire_cooldown -= dt;
+        ai.peek_time -= dt;
+
+        match ai.state {
+            AiState::Patrol | AiState::Chase => {
+                if visible && ai.peek_time <= 0. && health.current <= difficulty.retreat_health() {
+                    ai.cover = walls.find_cover(position, player_pos);
+                    if ai.cover.is_some() {
+                        ai.state = AiState::TakeCover;
+                        ai.cover_time = 0.;
+                        continue;
+                    }
+                }
+
+                if visible {
+                    if ai.state == AiState::Patrol {
+                        ai.reaction = difficulty.reaction_time();
+                    }
+                    ai.state = AiState::Chase;
+                } else if ai.state == AiState::Chase && ai.last_seen.is_none() {
+                    ai.state = AiState::Patrol;
+                }
+            }
+            AiState::TakeCover => {
+                if let Some(cover) = ai.cover {
+                    if position.distance(cover) < 50. {
+                        ai.cover_time += dt;
+                    }
+                }
+                // Pop back out after hiding for a while so the fight doesn't stall.
+                if ai.cover.is_none() || ai.cover_time > 3. {
+                    ai.cover = None;
+                    ai.peek_time = 2.;
+                    ai.state = AiState::Chase;
+                }
+            }
+        }
+    }
+}
+
+// The AI walks on the same momentum as players, so hits knock it around the same way.
+pub fn ai_move(
+    time: Res<Time>,
+    difficulty: Res<Difficulty>,
+    player_q: Query<&Transform, With<Player>>,
+    mut ai_q: Query<
+        (
+            &mut Transform,
+            &mut AiController,
+            &mut Momentum,
+            Has<HitStun>,
+        ),
+        Without<Player>,
+    >,
+    nav_grid: Res<NavGrid>,
+) {
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+    let player_pos = player_transform.translation.truncate();
+    let dt = time.delta_secs();
+
+    for (mut transform, mut ai, mut momentum, stunned) in ai_q.iter_mut() {
+        let position = transform.translation.truncate();
+
+        let target = match ai.state {
+            AiState::Patrol => {
+                let waypoint = ai.patrol[ai.patrol_index];
+                if ai.arrived(position, waypoint) {
+                    ai.patrol_index = (ai.patrol_index + 1) % ai.patrol.len();
+                }
+                waypoint
+            }
+            AiState::Chase => match ai.last_seen {
+                Some(seen) if seen == player_pos => {
+                    // Keep some distance instead of running into the player.
+                    if position.distance(player_pos) > 600. {
+                        player_pos
+                    } else {
+                        position
+                    }
+                }
+                Some(seen) => {
+                    if ai.arrived(position, seen) {
+                        ai.last_seen = None;
+                    }
+                    seen
+                }
+                None => position,
+            },
+            AiState::TakeCover => ai.cover.unwrap_or(position),
+        };
+
+        // Standing still still has to let any knockback play out.
+        let mut heading = Vec2::ZERO;
+        if position.distance(target) >= 1. {
+            let waypoint = ai.steer(position, target, &nav_grid, dt);
+            let to_target = waypoint - position;
+            if to_target.length() > 1. {
+                heading = to_target;
+            }
+        }
+
+        let mut speed_factor = difficulty.speed() / momentum.max_speed;
+        if stunned {
+            speed_factor *= STUN_SLOW;
+        }
+        let movement = momentum.step(heading, speed_factor, dt);
+        transform.translation.x += movement.x;
+        transform.translation.y += movement.y;
+    }
+}
+
+// Direction to aim so a bullet fired from `shooter` meets a target moving at `velocity`.
+fn lead_target(shooter: Vec2, target: Vec2, velocity: Vec2, speed: f32) -> Vec2 {
+    let offset = target - shooter;
+    let a = velocity.length_squared() - speed * speed;
+    let b = 2. * offset.dot(velocity);
+    let c = offset.length_squared();
+    let discriminant = b * b - 4. * a * c;
+
+    if a.abs() < f32::EPSILON || discriminant < 0. {
+        return target;
+    }
+
+    let sqrt = discriminant.sqrt();
+    let t1 = (-b + sqrt) / (2. * a);
+    let t2 = (-b - sqrt) / (2. * a);
+    let t = match (t1 > 0., t2 > 0.) {
+        (true, true) => t1.min(t2),
+        (true, false) => t1,
+        (false, true) => t2,
+        (false, false) => return target,
+    };
+
+    target + velocity * t
+}
+
+pub fn ai_shoot(
+    mut gun: Gunfire,
+    difficulty: Res<Difficulty>,
+    player_q: Query<&Transform, With<Player>>,
+    mut ai_q: Query<(Entity, &Transform, &mut AiController, &Children), Without<Player>>,
+    mut barrel_q: Query<&mut EnemyBarrel>,
+    walls: Walls,
+) {
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+    let player_pos = player_transform.translation.truncate();
+    let mut rng = rand::thread_rng();
+
+    for (entity, transform, mut ai, children) in ai_q.iter_mut() {
+        let position = transform.translation.truncate();
+
+        let aim_at = match ai.state {
+            AiState::Chase if ai.last_seen == Some(player_pos) => lead_target(
+                position,
+                player_pos,
+                ai.target_velocity * difficulty.lead_accuracy(),
+                AI_WEAPON.stats().bullet_speed,
+            ),
+            AiState::Chase => ai.last_seen.unwrap_or(player_pos),
+            AiState::Patrol => ai.patrol[ai.patrol_index],
+            AiState::TakeCover => player_pos,
+        };
+        let direction = aim_at - position;
+        let angle = direction.y.atan2(direction.x);
+
+        for &child in children.iter() {
+            if let Ok(mut barrel) = barrel_q.get_mut(child) {
+                barrel.angle = angle;
+            }
+        }
+
+        let can_fire = ai.state == AiState::Chase
+            && ai.last_seen == Some(player_pos)
+            && ai.reaction <= 0.
+            && ai.fire_cooldown <= 0.
+            && walls.has_line_of_sight(position, player_pos);
+
+        if can_fire {
+            ai.fire_cooldown = difficulty.fire_interval();
+            let error = difficulty.aim_error();
+            let angle = angle + rng.gen_range(-error..=error);
+
+            let bsp =
+                BulletSoundtrackPlayer::new("sounds/gunshot.ogg", Res::clone(&gun.asset_server));
+            BulletSoundtrackPlayer::play_bullet_sound(bsp, &mut gun.commands);
+            let fired_by = FiredBy {
+                entity,
+                owner: AI_OWNER.into(),
+            };
+            spawn_bullet(
+                &mut gun.commands,
+                &mut gun.meshes,
+                &mut gun.materials,
+                fired_by,
+                position,
+                angle,
+                &AI_WEAPON.stats(),
+            );
+        }
+    }
+}
+
+pub fn ai_bullet_hits(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionStarted>,
+    mut ai_q: Query<(Entity, &mut Health, &mut Momentum), With<AiController>>,
+    bullet_q: Query<(&Bullet, &FiredBy, &Velocity)>,
+    friendly_fire: Res<FriendlyFire>,
+) {
+    for collision in collisions.read() {
+        let Ok((bullet, fired_by, velocity)) = bullet_q.get(collision.a) else {
+            continue;
+        };
+        if !fired_by.can_hurt(AI_OWNER, &friendly_fire) {
+            continue;
+        }
+        if let Ok((entity, mut health, mut momentum)) = ai_q.get_mut(collision.b) {
+            health.current -= bullet.current_damage();
+            momentum.shove(
+                Vec2::new(velocity.x, velocity.y),
+                bullet.current_knockback(),
+            );
+            if bullet.stun_secs > 0. {
+                commands
+                    .entity(entity)
+                    .insert(HitStun::new(bullet.stun_secs));
+            }
+        }
+    }
+
+    for (entity, health, _) in ai_q.iter() {
+        if health.is_dead() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
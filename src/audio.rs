@@ -14,15 +14,17 @@ pub mod audioO {
                 track: asset_server.load::<AudioSource>(track),
             }
         }
-        pub fn play_track(soundtrack_player: SoundtrackPlayer, mut commands: Commands) {
-            commands.spawn((
-                AudioPlayer(soundtrack_player.track.clone()),
-                PlaybackSettings {
-                    mode: bevy::audio::PlaybackMode::Loop,
-                    volume: bevy::audio::Volume::new(0.2),
-                    ..default()
-                },
-            ));
+        pub fn play_track(soundtrack_player: SoundtrackPlayer, mut commands: Commands) -> Entity {
+            commands
+                .spawn((
+                    AudioPlayer(soundtrack_player.track.clone()),
+                    PlaybackSettings {
+                        mode: bevy::audio::PlaybackMode::Loop,
+                        volume: bevy::audio::Volume::new(0.2),
+                        ..default()
+                    },
+                ))
+                .id()
         }
     }
 
@@ -78,4 +80,87 @@ pub mod audioO {
             ));
         }
     }
+
+    #[derive(Resource)]
+    pub struct BlockBreakSoundtrackPlayer {
+        pub sound: Handle<AudioSource>,
+    }
+
+    impl BlockBreakSoundtrackPlayer {
+        pub fn new(sound: &str, asset_server: &Res<AssetServer>) -> Self {
+            BlockBreakSoundtrackPlayer {
+                sound: asset_server.load::<AudioSource>(sound),
+            }
+        }
+        pub fn play_break_sound(
+            soundtrack_player: &BlockBreakSoundtrackPlayer,
+            commands: &mut Commands,
+        ) {
+            // The impact sound slowed down makes a passable crunch.
+            commands.spawn((
+                AudioPlayer(soundtrack_player.sound.clone()),
+                PlaybackSettings {
+                    mode: bevy::audio::PlaybackMode::Once,
+                    volume: bevy::audio::Volume::new(0.5),
+                    speed: 0.5,
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    #[derive(Resource)]
+    pub struct HitConfirmSoundtrackPlayer {
+        pub sound: Handle<AudioSource>,
+    }
+
+    impl HitConfirmSoundtrackPlayer {
+        pub fn new(sound: &str, asset_server: &Res<AssetServer>) -> Self {
+            HitConfirmSoundtrackPlayer {
+                sound: asset_server.load::<AudioSource>(sound),
+            }
+        }
+        pub fn play_hit_sound(
+            soundtrack_player: &HitConfirmSoundtrackPlayer,
+            commands: &mut Commands,
+        ) {
+            commands.spawn((
+                AudioPlayer(soundtrack_player.sound.clone()),
+                PlaybackSettings {
+                    mode: bevy::audio::PlaybackMode::Once,
+                    volume: bevy::audio::Volume::new(0.4),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    #[derive(Resource)]
+    pub struct ExplosionSoundtrackPlayer {
+        pub sound: Handle<AudioSource>,
+    }
+
+    impl ExplosionSoundtrackPlayer {
+        pub fn new(sound: &str, asset_server: &Res<AssetServer>) -> Self {
+            ExplosionSoundtrackPlayer {
+                sound: asset_server.load::<AudioSource>(sound),
+            }
+        }
+        pub fn play_explosion_sound(
+            soundtrack_player: &ExplosionSoundtrackPlayer,
+            commands: &mut Commands,
+            volume: f32,
+        ) {
+            // Slowed right down, the impact sound turns into a boom.
+            commands.spawn((
+                AudioPlayer(soundtrack_player.sound.clone()),
+                PlaybackSettings {
+                    mode: bevy::audio::PlaybackMode::Once,
+                    volume: bevy::audio::Volume::new(volume),
+                    speed: 0.3,
+                    ..default()
+                },
+            ));
+        }
+    }
 }
@@ -1,22 +1,71 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
 
 use crate::colliders::colliders_o::{self, ColliderType};
 
 #[derive(Component)]
 
 pub struct Block {
+    // Convex polygon in world space, counter-clockwise.
     #[allow(dead_code)]
-    pub points: ((f32, f32), (f32, f32), (f32, f32), (f32, f32)),
+    pub points: Vec<Vec2>,
 }
 
 #[allow(warnings)]
 impl Block {
-    pub fn get_block_center(
-        points: ((f32, f32), (f32, f32), (f32, f32), (f32, f32)),
-    ) -> (f32, f32) {
-        let centerx = (points.0 .0 + points.2 .0) / 2.;
-        let centery = (points.0 .1 + points.2 .1) / 2.;
-        return (centerx, centery);
+    pub fn from_rect(min: Vec2, max: Vec2) -> Block {
+        Block {
+            points: vec![
+                Vec2::new(min.x, min.y),
+                Vec2::new(max.x, min.y),
+                Vec2::new(max.x, max.y),
+                Vec2::new(min.x, max.y),
+            ],
+        }
+    }
+
+    // Takes the convex hull of `points`, so they can be given in any order, then
+    // rotates it by `rotation` radians around its center.
+    pub fn from_points(points: &[(f32, f32)], rotation: f32) -> Block {
+        let hull = convex_hull(points.iter().map(|&(x, y)| Vec2::new(x, y)).collect());
+        let center = Self::center_of(&hull);
+        let rotation = Vec2::from_angle(rotation);
+
+        Block {
+            points: hull
+                .into_iter()
+                .map(|point| center + rotation.rotate(point - center))
+                .collect(),
+        }
+    }
+
+    pub fn center_of(points: &[Vec2]) -> Vec2 {
+        points.iter().copied().sum::<Vec2>() / points.len().max(1) as f32
+    }
+
+    fn mesh(points: &[Vec2], center: Vec2) -> Mesh {
+        let positions: Vec<[f32; 3]> = points
+            .iter()
+            .map(|point| [point.x - center.x, point.y - center.y, 0.])
+            .collect();
+        let count = positions.len() as u32;
+        let indices = (1..count.saturating_sub(1))
+            .flat_map(|i| [0, i, i + 1])
+            .collect();
+
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; positions.len()])
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.]; positions.len()])
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_indices(Indices::U32(indices))
     }
 
     pub fn spawn_block(
@@ -24,28 +73,45 @@ impl Block {
         mut commands: &mut Commands,
         mut meshes: &mut ResMut<Assets<Mesh>>,
         mut materials: &mut ResMut<Assets<ColorMaterial>>,
-    ) {
-        // Create a rectangle mesh
-        let rectangle = Rectangle::new(
-            block.points.2 .0 - block.points.0 .0,
-            block.points.3 .1 - block.points.1 .1,
-        );
-
-        let center = Self::get_block_center(block.points);
-
-        commands.spawn((
-            Block {
-                points: block.points,
-            },
-            colliders_o::BlockCollider {
-                points: block.points,
-                collider_type: ColliderType::Block,
-                width: block.points.2 .0 - block.points.0 .0,
-                height: block.points.3 .1 - block.points.1 .1,
-            },
-            Mesh2d(meshes.add(rectangle)),
-            MeshMaterial2d(materials.add(ColorMaterial::from(Color::srgb(0.5, 0.5, 0.5)))),
-            Transform::from_xyz(center.0, center.1, 1.0),
-        ));
+    ) -> Entity {
+        let center = Self::center_of(&block.points);
+
+        commands
+            .spawn((
+                colliders_o::BlockCollider::new(ColliderType::Block, block.points.clone()),
+                Mesh2d(meshes.add(Self::mesh(&block.points, center))),
+                MeshMaterial2d(materials.add(ColorMaterial::from(Color::srgb(0.5, 0.5, 0.5)))),
+                Transform::from_xyz(center.x, center.y, 1.0),
+                block,
+            ))
+            .id()
     }
 }
+
+// Andrew's monotone chain. Returns the hull counter-clockwise without repeating the
+// first point.
+fn convex_hull(mut points: Vec<Vec2>) -> Vec<Vec2> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    let cross = |o: Vec2, a: Vec2, b: Vec2| (a - o).perp_dot(b - o);
+    let half_hull = |points: &mut dyn Iterator<Item = &Vec2>| {
+        let mut hull: Vec<Vec2> = Vec::new();
+        for &point in points {
+            while hull.len() >= 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+        hull
+    };
+
+    let mut hull = half_hull(&mut points.iter());
+    hull.extend(half_hull(&mut points.iter().rev()));
+    hull
+}
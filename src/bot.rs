@@ -0,0 +1,519 @@
+use b::{bullets, colliders, map, movement, navigation, net, player, weapons};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::math::Vec2;
+use colliders::colliders_o::circle_polygon_push;
+use map::{MapBounds, MapData};
+use movement::Momentum;
+use navigation::{NavGrid, NAV_CELL_SIZE};
+use net::{ClientMessage, MessageBuffer, ServerMessage, Welcome};
+use player::Player;
+use rand::Rng;
+use weapons::WeaponKind;
+
+const PLAYER_RADIUS: f32 = 70.0;
+// Close enough to a waypoint to head for the next one.
+const ARRIVE_RADIUS: f32 = 40.0;
+const AIM_RANGE: f32 = 1500.0;
+const SHOT_INTERVAL: f32 = 0.4;
+// How long to wait before looking for another path when there was none.
+const PATH_RETRY: Duration = Duration::from_millis(500);
+const USAGE: &str =
+    "usage: bot [--server addr] [--count n] [--ramp-ms ms] [--duration secs] [--tick-hz hz]";
+
+struct BotConfig {
+    server: String,
+    count: usize,
+    ramp: Duration,
+    duration: Duration,
+    tick_hz: u32,
+}
+
+impl BotConfig {
+    fn from_args() -> Self {
+        let mut config = BotConfig {
+            server: String::from("127.0.0.1:8080"),
+            count: 8,
+            ramp: Duration::ZERO,
+            duration: Duration::from_secs(60),
+            tick_hz: 60,
+        };
+
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut i = 0;
+        while i < args.len() {
+            let flag = args[i].as_str();
+            let Some(value) = args.get(i + 1) else {
+                usage_error(&format!("{} expects a value", flag));
+            };
+            match flag {
+                "--server" => config.server = value.clone(),
+                "--count" => config.count = parse_arg(flag, value),
+                "--ramp-ms" => config.ramp = Duration::from_millis(parse_arg(flag, value)),
+                "--duration" => config.duration = Duration::from_secs(parse_arg(flag, value)),
+                "--tick-hz" => config.tick_hz = parse_arg(flag, value),
+                other => usage_error(&format!("Unknown argument: {}", other)),
+            }
+            i += 2;
+        }
+
+        // A tick rate of zero would mean an endless tick.
+        if config.tick_hz == 0 {
+            usage_error("--tick-hz must be at least 1");
+        }
+
+        config
+    }
+}
+
+fn parse_arg<T: FromStr>(flag: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("{} expects a number, got {:?}", flag, value)))
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+// Writes as much of `pending` as the socket takes without blocking and drops that much
+// from the front, so a frame cut short by a full send buffer is finished next time
+// instead of being followed by the start of another one.
+fn flush(stream: &mut TcpStream, pending: &mut Vec<u8>) -> io::Result<usize> {
+    let mut written = 0;
+    while written < pending.len() {
+        match stream.write(&pending[written..]) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => written += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        }
+    }
+    pending.drain(..written);
+    Ok(written)
+}
+
+// Send times of the updates a bot has written, keyed by the update contents, so that
+// other bots in this process can measure how long the server took to relay them.
+type SentLog = Mutex<HashMap<u64, Instant>>;
+
+#[derive(Default)]
+struct Stats {
+    connected: AtomicUsize,
+    failed: AtomicUsize,
+    disconnected: AtomicUsize,
+    sent_msgs: AtomicU64,
+    sent_bytes: AtomicU64,
+    recv_msgs: AtomicU64,
+    recv_bytes: AtomicU64,
+    bad_msgs: AtomicU64,
+}
+
+struct Shared {
+    stats: Stats,
+    sent_logs: Vec<SentLog>,
+    latencies: Vec<Mutex<Vec<u32>>>,
+    running: AtomicBool,
+    // Built from the map the server sends the first bot to connect.
+    map: OnceLock<BotMap>,
+}
+
+struct BotMap {
+    nav_grid: NavGrid,
+    bounds: MapBounds,
+    // Outline of every wall, to keep bots from walking through them.
+    walls: Vec<Vec<Vec2>>,
+}
+
+impl BotMap {
+    fn new(map: &MapData) -> Self {
+        let walls: Vec<Vec<Vec2>> = map.blocks().map(|block| block.points).collect();
+        BotMap {
+            nav_grid: NavGrid::build(
+                &walls,
+                map.bounds.min(),
+                map.bounds.max(),
+                NAV_CELL_SIZE,
+                PLAYER_RADIUS,
+            ),
+            bounds: map.bounds,
+            walls,
+        }
+    }
+}
+
+fn main() {
+    let config = BotConfig::from_args();
+    let shared = Arc::new(Shared {
+        stats: Stats::default(),
+        sent_logs: (0..config.count)
+            .map(|_| Mutex::new(HashMap::new()))
+            .collect(),
+        latencies: (0..config.count).map(|_| Mutex::new(Vec::new())).collect(),
+        running: AtomicBool::new(true),
+        map: OnceLock::new(),
+    });
+
+    println!(
+        "Starting {} bots against {} ({} Hz, ramp {} ms)",
+        config.count,
+        config.server,
+        config.tick_hz,
+        config.ramp.as_millis()
+    );
+
+    let start = Instant::now();
+    let mut handles = Vec::new();
+    let mut spawned = 0;
+    let mut next_spawn = start;
+    let mut next_report = start + Duration::from_secs(1);
+    let mut totals = Totals::default();
+
+    while start.elapsed() < config.duration {
+        while spawned < config.count && Instant::now() >= next_spawn {
+            let shared = Arc::clone(&shared);
+            let server = config.server.clone();
+            let tick_hz = config.tick_hz;
+            let index = spawned;
+            handles.push(thread::spawn(move || {
+                run_bot(index, server, tick_hz, shared)
+            }));
+            spawned += 1;
+            next_spawn += config.ramp;
+        }
+
+        if Instant::now() >= next_report {
+            report(&shared, spawned, start.elapsed(), &mut totals);
+            next_report += Duration::from_secs(1);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    shared.running.store(false, Ordering::Relaxed);
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    println!(
+        "Summary: {} bots, {} connect failures, {} disconnects, {} msgs sent, {} msgs received, worst p95 latency {:.1} ms at {} bots",
+        spawned,
+        shared.stats.failed.load(Ordering::Relaxed),
+        shared.stats.disconnected.load(Ordering::Relaxed),
+        shared.stats.sent_msgs.load(Ordering::Relaxed),
+        shared.stats.recv_msgs.load(Ordering::Relaxed),
+        totals.worst_p95 as f32 / 1000.,
+        totals.worst_p95_bots,
+    );
+}
+
+#[derive(Default)]
+struct Totals {
+    sent_msgs: u64,
+    sent_bytes: u64,
+    recv_msgs: u64,
+    recv_bytes: u64,
+    worst_p95: u32,
+    worst_p95_bots: usize,
+}
+
+fn report(shared: &Shared, spawned: usize, elapsed: Duration, totals: &mut Totals) {
+    let stats = &shared.stats;
+    let sent_msgs = stats.sent_msgs.load(Ordering::Relaxed);
+    let sent_bytes = stats.sent_bytes.load(Ordering::Relaxed);
+    let recv_msgs = stats.recv_msgs.load(Ordering::Relaxed);
+    let recv_bytes = stats.recv_bytes.load(Ordering::Relaxed);
+
+    let mut samples: Vec<u32> = Vec::new();
+    for latencies in &shared.latencies {
+        samples.append(&mut latencies.lock().unwrap());
+    }
+    samples.sort_unstable();
+
+    let connected = stats.connected.load(Ordering::Relaxed);
+    let latency = if samples.is_empty() {
+        String::from("latency n/a")
+    } else {
+        let avg = samples.iter().map(|&s| s as u64).sum::<u64>() / samples.len() as u64;
+        let p95 = samples[(samples.len() - 1) * 95 / 100];
+        let max = samples[samples.len() - 1];
+        if p95 > totals.worst_p95 {
+            totals.worst_p95 = p95;
+            totals.worst_p95_bots = connected;
+        }
+        format!(
+            "latency avg {:.1} ms p95 {:.1} ms max {:.1} ms",
+            avg as f32 / 1000.,
+            p95 as f32 / 1000.,
+            max as f32 / 1000.
+        )
+    };
+
+    println!(
+        "[{:>4}s] bots {}/{} (failed {}, dropped {}) | sent {} msg/s {:.1} KB/s | recv {} msg/s {:.1} KB/s | bad {} | {}",
+        elapsed.as_secs(),
+        connected,
+        spawned,
+        stats.failed.load(Ordering::Relaxed),
+        stats.disconnected.load(Ordering::Relaxed),
+        sent_msgs - totals.sent_msgs,
+        (sent_bytes - totals.sent_bytes) as f32 / 1024.,
+        recv_msgs - totals.recv_msgs,
+        (recv_bytes - totals.recv_bytes) as f32 / 1024.,
+        stats.bad_msgs.load(Ordering::Relaxed),
+        latency,
+    );
+
+    totals.sent_msgs = sent_msgs;
+    totals.sent_bytes = sent_bytes;
+    totals.recv_msgs = recv_msgs;
+    totals.recv_bytes = recv_bytes;
+}
+
+fn update_key(player: &Player) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    player.id.hash(&mut hasher);
+    player.position.0.to_bits().hash(&mut hasher);
+    player.position.1.to_bits().hash(&mut hasher);
+    player.angle.to_bits().hash(&mut hasher);
+    player.just_shot.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn bot_index(id: &str) -> Option<usize> {
+    id.strip_prefix("bot")?.parse().ok()
+}
+
+fn random_target(rng: &mut impl Rng, nav_grid: &NavGrid, bounds: &MapBounds) -> Vec2 {
+    let limit = bounds.playable_half_extents() - Vec2::splat(PLAYER_RADIUS);
+    loop {
+        let target = Vec2::new(
+            rng.gen_range(-limit.x..limit.x),
+            rng.gen_range(-limit.y..limit.y),
+        );
+        if nav_grid.is_walkable(target) {
+            return target;
+        }
+    }
+}
+
+fn run_bot(index: usize, server: String, tick_hz: u32, shared: Arc<Shared>) {
+    let stats = &shared.stats;
+    let id = format!("bot{:04}", index);
+
+    let mut stream = match TcpStream::connect(&server) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("{} failed to connect: {}", id, e);
+            stats.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    if let Err(e) = stream.write_all(id.as_bytes()) {
+        eprintln!("{} couldn't send id to server: {}", id, e);
+        stats.failed.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let Welcome { map: source, spawn } = match net::read_message(&mut stream) {
+        Ok(welcome) => welcome,
+        Err(e) => {
+            eprintln!("{} couldn't read map from server: {}", id, e);
+            stats.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    let BotMap {
+        nav_grid,
+        bounds,
+        walls,
+    } = match shared.map.get() {
+        Some(map) => map,
+        None => match source.load() {
+            Ok(map) => shared.map.get_or_init(|| BotMap::new(&map)),
+            Err(e) => {
+                eprintln!("{} couldn't load map {}: {}", id, source.path(), e);
+                stats.failed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        },
+    };
+    stream
+        .set_nonblocking(true)
+        .expect("Failed to set non-blocking");
+    stream.set_nodelay(true).ok();
+    stats.connected.fetch_add(1, Ordering::Relaxed);
+
+    let mut rng = rand::thread_rng();
+    let tick = Duration::from_secs_f32(1. / tick_hz as f32);
+    let mut position = Vec2::new(spawn.0, spawn.1);
+    let mut path: Vec<Vec2> = Vec::new();
+    let mut next_path = Instant::now();
+    let mut momentum = Momentum::default();
+    let mut shot_cooldown = 0.;
+    let mut others: HashMap<String, (f32, f32)> = HashMap::new();
+    let mut messages = MessageBuffer::default();
+    let mut buffer = [0; 4096];
+    let mut outgoing: Vec<u8> = Vec::new();
+    let mut last_prune = Instant::now();
+    let mut next_tick = Instant::now();
+
+    'outer: while shared.running.load(Ordering::Relaxed) {
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    println!("{} disconnected by server", id);
+                    break 'outer;
+                }
+                Ok(n) => {
+                    stats.recv_bytes.fetch_add(n as u64, Ordering::Relaxed);
+                    messages.extend(&buffer[..n]);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("{} error reading from stream: {}", id, e);
+                    break 'outer;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let dropped = messages.dropped;
+        for message in messages.drain::<ServerMessage>() {
+            stats.recv_msgs.fetch_add(1, Ordering::Relaxed);
+            let update = match message {
+                ServerMessage::Update(update) => update,
+                // Walked into another player; go where the server put us.
+                ServerMessage::Correction { position: corrected } => {
+                    position = Vec2::new(corrected.0, corrected.1);
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(sender) = bot_index(&update.id) {
+                if let Some(sent) = shared
+                    .sent_logs
+                    .get(sender)
+                    .and_then(|log| log.lock().unwrap().get(&update_key(&update)).copied())
+                {
+                    let micros = now.duration_since(sent).as_micros() as u32;
+                    shared.latencies[index].lock().unwrap().push(micros);
+                }
+            }
+            others.insert(update.id, update.position);
+        }
+        stats
+            .bad_msgs
+            .fetch_add(messages.dropped - dropped, Ordering::Relaxed);
+
+        let dt = tick.as_secs_f32();
+        // With no way to the target, the bot stands still for a moment rather than
+        // running A* again every tick.
+        if path.is_empty() && now >= next_path {
+            let goal = random_target(&mut rng, nav_grid, bounds);
+            match nav_grid.find_path(position, goal) {
+                Some(found) => path = found,
+                None => next_path = now + PATH_RETRY,
+            }
+        }
+
+        let waypoint = path.first().copied().unwrap_or(position);
+        let to_target = waypoint - position;
+        if to_target.length() <= ARRIVE_RADIUS && !path.is_empty() {
+            path.remove(0);
+        }
+        position += momentum.step(to_target, 1., dt);
+        for wall in walls {
+            if let Some(push) = circle_polygon_push(position, PLAYER_RADIUS, wall) {
+                position += push;
+                momentum.stop_against(push.normalize_or_zero());
+            }
+        }
+
+        let nearest = others
+            .values()
+            .map(|&other| (other, bullets::get_length((position.x, position.y), other)))
+            .filter(|&(_, dist)| dist < AIM_RANGE)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut just_shot = false;
+        shot_cooldown -= dt;
+        let angle = if let Some((other, _)) = nearest {
+            if shot_cooldown <= 0. {
+                just_shot = true;
+                shot_cooldown = SHOT_INTERVAL;
+            }
+            (other.1 - position.y).atan2(other.0 - position.x)
+        } else {
+            to_target.y.atan2(to_target.x)
+        };
+
+        let update = Player {
+            id: id.clone(),
+            position: (position.x, position.y),
+            angle,
+            just_shot,
+            weapon: WeaponKind::default(),
+            shot_seed: rng.gen(),
+            shot_angle: angle,
+            damage_boost: false,
+            sprinting: false,
+            dashing: false,
+            velocity: momentum.velocity.into(),
+            heading: momentum.heading.into(),
+            dead: false,
+            protected: false,
+            current_health: 1000,
+        };
+
+        let mut log = shared.sent_logs[index].lock().unwrap();
+        log.insert(update_key(&update), Instant::now());
+        if last_prune.elapsed() > Duration::from_secs(1) {
+            log.retain(|_, sent| sent.elapsed() < Duration::from_secs(5));
+            last_prune = Instant::now();
+        }
+        drop(log);
+
+        // If the server has fallen a whole message behind, this update is skipped rather
+        // than queued; the next one says where the bot is anyway.
+        if outgoing.len() < net::MAX_MESSAGE {
+            outgoing.extend(net::encode(&ClientMessage::Update(update)));
+            stats.sent_msgs.fetch_add(1, Ordering::Relaxed);
+        }
+        match flush(&mut stream, &mut outgoing) {
+            Ok(written) => {
+                stats
+                    .sent_bytes
+                    .fetch_add(written as u64, Ordering::Relaxed);
+            }
+            Err(e) => {
+                eprintln!("{} failed to write to stream: {}", id, e);
+                break;
+            }
+        }
+
+        next_tick += tick;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep(next_tick - now);
+        } else {
+            next_tick = now;
+        }
+    }
+
+    stats.connected.fetch_sub(1, Ordering::Relaxed);
+    if shared.running.load(Ordering::Relaxed) {
+        stats.disconnected.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,158 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    bullets::Bullet,
+    colliders::colliders_o::{BlockCollider, CircleCollider},
+};
+
+// Big enough that most blocks only cover a handful of cells, small enough that a
+// bullet's cell rarely holds more than a few blocks.
+pub const BROADPHASE_CELL_SIZE: f32 = 500.0;
+
+// Uniform grid of bounding boxes. An entry is stored in every cell its box overlaps,
+// so a query only has to look at the cells under the box being tested.
+#[derive(Clone, Debug)]
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialHash {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    fn cell(&self, position: Vec2) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    // A NaN or infinite corner covers no cells, rather than every cell there is.
+    fn cells_in(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = (i32, i32)> {
+        let ((min_x, min_y), (max_x, max_y)) = if min.is_finite() && max.is_finite() {
+            (self.cell(min), self.cell(max))
+        } else {
+            ((0, 0), (-1, -1))
+        };
+        (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+    }
+
+    pub fn insert(&mut self, entity: Entity, min: Vec2, max: Vec2) {
+        for cell in self.cells_in(min, max).collect::<Vec<_>>() {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+    }
+
+    // Fills `out` with every entry whose box might overlap min..max, each once.
+    pub fn query(&self, min: Vec2, max: Vec2, out: &mut Vec<Entity>) {
+        out.clear();
+        for cell in self.cells_in(min, max) {
+            if let Some(entities) = self.cells.get(&cell) {
+                out.extend_from_slice(entities);
+            }
+        }
+        // Boxes spanning several cells show up once per cell.
+        out.sort_unstable();
+        out.dedup();
+    }
+
+    pub fn query_circle(&self, center: Vec2, radius: f32, out: &mut Vec<Entity>) {
+        self.query(
+            center - Vec2::splat(radius),
+            center + Vec2::splat(radius),
+            out,
+        );
+    }
+}
+
+// Every block collider in the world, rebuilt each tick by `update_block_broadphase`.
+#[derive(Resource, Deref)]
+pub struct BlockBroadphase(pub SpatialHash);
+
+impl Default for BlockBroadphase {
+    fn default() -> Self {
+        BlockBroadphase(SpatialHash::new(BROADPHASE_CELL_SIZE))
+    }
+}
+
+// Every body that isn't a bullet, rebuilt each tick by `update_body_broadphase` once
+// everything has moved.
+#[derive(Resource, Deref)]
+pub struct BodyBroadphase(pub SpatialHash);
+
+impl Default for BodyBroadphase {
+    fn default() -> Self {
+        BodyBroadphase(SpatialHash::new(BROADPHASE_CELL_SIZE))
+    }
+}
+
+pub struct BroadphasePlugin;
+
+impl Plugin for BroadphasePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockBroadphase>()
+            .init_resource::<BodyBroadphase>()
+            .add_systems(PreUpdate, update_block_broadphase);
+    }
+}
+
+pub fn update_block_broadphase(
+    mut broadphase: ResMut<BlockBroadphase>,
+    blocks: Query<(Entity, &BlockCollider)>,
+) {
+    broadphase.0.clear();
+    for (entity, block) in blocks.iter() {
+        broadphase.0.insert(entity, block.min, block.max);
+    }
+}
+
+pub fn update_body_broadphase(
+    mut broadphase: ResMut<BodyBroadphase>,
+    bodies: Query<(Entity, &Transform, &CircleCollider), Without<Bullet>>,
+) {
+    broadphase.0.clear();
+    for (entity, transform, collider) in bodies.iter() {
+        let center = transform.translation.truncate();
+        let extent = Vec2::splat(collider.radius);
+        broadphase
+            .0
+            .insert(entity, center - extent, center + extent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_finds_boxes_spanning_several_cells_once() {
+        let mut hash = SpatialHash::new(100.);
+        let entity = Entity::from_raw(1);
+        hash.insert(entity, Vec2::splat(-150.), Vec2::splat(150.));
+
+        let mut found = Vec::new();
+        hash.query(Vec2::splat(-200.), Vec2::splat(200.), &mut found);
+        assert_eq!(found, vec![entity]);
+    }
+
+    #[test]
+    fn non_finite_boxes_cover_no_cells() {
+        let mut hash = SpatialHash::new(100.);
+        hash.insert(Entity::from_raw(1), Vec2::splat(f32::NAN), Vec2::ZERO);
+        hash.insert(Entity::from_raw(2), Vec2::ZERO, Vec2::splat(f32::INFINITY));
+        assert!(hash.cells.is_empty());
+
+        let mut found = vec![Entity::from_raw(3)];
+        hash.query_circle(Vec2::new(f32::NEG_INFINITY, 0.), 10., &mut found);
+        assert!(found.is_empty());
+    }
+}
@@ -1,105 +1,256 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use serde::*;
 
 use crate::{
     audio::audioO::{BulletImpactSoundtrackPlayer, BulletSoundtrackPlayer},
-    colliders::colliders_o::{CircleCollider, ColliderType},
-    game::{MAP_HEIGHT, MAP_WIDTH},
+    colliders::colliders_o::{
+        line_through_polygon, BlockCollider, CircleCollider, ColliderType, CollisionStarted,
+    },
+    destruction::{BlockHit, Destructible},
+    explosions::Explosive,
+    map::{MapBounds, PickupKind},
+    pickups::{boost_damage, PowerUps},
     player::*,
+    weapons::{spawn_shot, Shot, Weapon, WeaponFired, WeaponStats},
 };
 
+// Bullets fired by AI enemies all carry this owner, so they count as one side.
+pub const AI_OWNER: &str = "ai";
+// What's left of a bullet's damage by the end of its range.
+const MIN_FALLOFF: f32 = 0.5;
+// What's left of a bullet's damage each time it goes through a block.
+const PENETRATION_FALLOFF: f32 = 0.6;
+// Gap left between a bullet and the block it bounced off or went through, so the next
+// sweep doesn't start out touching it.
+const SURFACE_GAP: f32 = 1.;
+
 #[derive(Component, Serialize, Deserialize, Debug)]
 pub struct Bullet {
     #[allow(dead_code)]
     pub velocity: Velocity,
     pub position: (f32, f32),
+    // Where the bullet was before the last `move_bullets`, so collisions can sweep the
+    // whole step instead of only testing where it landed.
+    pub previous: (f32, f32),
+    pub damage: i32,
+    // Full damage up to this distance, then less and less until `range`.
+    pub falloff_start: f32,
+    // How far it flies before it's gone.
+    pub range: f32,
+    pub travelled: f32,
+    pub ricochets_left: u32,
+    pub penetration: f32,
+    pub knockback: f32,
+    pub stun_secs: f32,
+}
+
+impl Bullet {
+    pub fn current_damage(&self) -> i32 {
+        if self.travelled <= self.falloff_start {
+            return self.damage;
+        }
+        let t = ((self.travelled - self.falloff_start) / (self.range - self.falloff_start).max(1.))
+            .clamp(0., 1.);
+        (self.damage as f32 * (1. - (1. - MIN_FALLOFF) * t)).round() as i32
+    }
+
+    // Spent bullets push as much less as they hurt.
+    pub fn current_knockback(&self) -> f32 {
+        if self.damage <= 0 {
+            return self.knockback;
+        }
+        self.knockback * self.current_damage() as f32 / self.damage as f32
+    }
+}
+
+// Fired by the local player, as opposed to a bullet replayed from another client.
+#[derive(Component)]
+pub struct LocalShot;
+
+// Whoever fired the bullet, so a kill can be put down to them. `owner` is their player
+// id, or `AI_OWNER`.
+#[derive(Component, Clone, Debug)]
+pub struct FiredBy {
+    pub entity: Entity,
+    pub owner: String,
+}
+
+impl FiredBy {
+    // Without friendly fire a bullet can't hurt whoever fired it or anyone on their side.
+    pub fn can_hurt(&self, victim: &str, friendly_fire: &FriendlyFire) -> bool {
+        friendly_fire.0 || self.owner != victim
+    }
 }
 
+// Whether bullets hurt their own side. Off unless the client is started with
+// `--friendly-fire`.
+#[derive(Resource, Default, Debug)]
+pub struct FriendlyFire(pub bool);
+
 #[derive(Component, Serialize, Deserialize, Debug)]
 pub struct Velocity {
     pub x: f32,
     pub y: f32,
 }
 
+// What it takes to put bullets in the world and be heard firing them.
+#[derive(SystemParam)]
+pub struct Gunfire<'w, 's> {
+    pub commands: Commands<'w, 's>,
+    pub asset_server: Res<'w, AssetServer>,
+    pub meshes: ResMut<'w, Assets<Mesh>>,
+    pub materials: ResMut<'w, Assets<ColorMaterial>>,
+}
+
+// Pulls the local player's trigger: held down for automatic weapons, once per click
+// otherwise. Whether a round actually goes out is up to the `Weapon`.
 pub fn shoot(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut queries: ParamSet<(
-        Query<Entity, With<Player>>,
-        Query<&Transform, With<Barrel>>,
-        Query<&Transform, With<Player>>,
-    )>,
+    mut gun: Gunfire,
+    mut player_q: Query<(Entity, &Player, &Transform, &mut Weapon, &PowerUps)>,
+    barrel_q: Query<&Transform, With<Barrel>>,
     mouse: Res<ButtonInput<MouseButton>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut fired: EventWriter<WeaponFired>,
 ) {
-    if mouse.just_pressed(MouseButton::Left) {
-        let bullet_sound = "sounds/gunshot.ogg";
-        let bsp = BulletSoundtrackPlayer::new(bullet_sound, asset_server);
-        BulletSoundtrackPlayer::play_bullet_sound(bsp, &mut commands);
-
-        if let Ok(barrel_transform) = queries.p1().get_single() {
-            let angle = barrel_transform.rotation.to_euler(EulerRot::XYZ).2;
-            let bullet_position = barrel_transform.translation
-                + Vec3::new(angle.cos() * 140.0, angle.sin() * 140.0, 0.0);
-            let velocity = Vec2::new(angle.cos(), angle.sin()) * 3500.0;
-            let bullet = Bullet {
+    let Ok((entity, player, player_transform, mut weapon, power_ups)) = player_q.get_single_mut()
+    else {
+        return;
+    };
+    let trigger = if weapon.stats.automatic {
+        mouse.pressed(MouseButton::Left)
+    } else {
+        mouse.just_pressed(MouseButton::Left)
+    };
+    if !trigger {
+        return;
+    }
+    let Ok(barrel_transform) = barrel_q.get_single() else {
+        return;
+    };
+    if !weapon.try_fire() {
+        return;
+    }
+
+    let bullet_sound = "sounds/gunshot.ogg";
+    let bsp = BulletSoundtrackPlayer::new(bullet_sound, Res::clone(&gun.asset_server));
+    BulletSoundtrackPlayer::play_bullet_sound(bsp, &mut gun.commands);
+
+    let angle = barrel_transform.rotation.to_euler(EulerRot::XYZ).2;
+    let player_pos = player_transform.translation.truncate();
+    let fired_by = FiredBy {
+        entity,
+        owner: player.id.clone(),
+    };
+    let mut stats = weapon.stats;
+    if power_ups.active(PickupKind::DamageBoost) {
+        boost_damage(&mut stats);
+    }
+    let seed = rand::random();
+    let shot = Shot {
+        fired_by,
+        origin: player_pos,
+        angle,
+        seed,
+    };
+    for bullet in spawn_shot(
+        &mut gun.commands,
+        &mut gun.meshes,
+        &mut gun.materials,
+        shot,
+        &stats,
+    ) {
+        gun.commands.entity(bullet).insert(LocalShot);
+    }
+    fired.send(WeaponFired { angle, seed });
+}
+
+pub fn spawn_bullet(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    fired_by: FiredBy,
+    shooter_pos: Vec2,
+    angle: f32,
+    stats: &WeaponStats,
+) -> Entity {
+    let position = shooter_pos + Vec2::new(angle.cos(), angle.sin()) * 80.;
+    let velocity = Vec2::new(angle.cos(), angle.sin()) * stats.bullet_speed;
+    let (size, color) = if stats.explosive.is_some() {
+        (18., Color::hsl(30., 0.9, 0.5))
+    } else {
+        (10., Color::WHITE)
+    };
+    let bullet = commands
+        .spawn((
+            Bullet {
                 velocity: Velocity {
                     x: velocity.x,
                     y: velocity.y,
                 },
-                position: (
-                    queries.p2().single().translation.x + angle.cos() * 80.,
-                    queries.p2().single().translation.y + angle.sin() * 80.,
-                ),
-            };
-            commands.spawn((
-                bullet,
-                CircleCollider {
-                    collider_type: ColliderType::Bullet,
-                    radius: 10.0,
-                },
-                Mesh2d(meshes.add(Circle::new(10.0))),
-                MeshMaterial2d(materials.add(ColorMaterial::from(Color::WHITE))),
-                Transform::from_translation(bullet_position),
-                Velocity {
-                    x: velocity.x,
-                    y: velocity.y,
-                },
-            ));
-        }
+                position: (position.x, position.y),
+                previous: (position.x, position.y),
+                damage: stats.damage,
+                falloff_start: stats.falloff_start,
+                range: stats.range,
+                travelled: 0.,
+                ricochets_left: stats.ricochets,
+                penetration: stats.penetration,
+                knockback: stats.knockback,
+                stun_secs: stats.stun_secs,
+            },
+            CircleCollider {
+                collider_type: ColliderType::Bullet,
+                radius: 10.0,
+            },
+            Mesh2d(meshes.add(Circle::new(size))),
+            MeshMaterial2d(materials.add(ColorMaterial::from(color))),
+            Transform::from_xyz(position.x, position.y, 0.0),
+            Velocity {
+                x: velocity.x,
+                y: velocity.y,
+            },
+            fired_by,
+        ))
+        .id();
+    if let Some(explosive) = stats.explosive {
+        commands.entity(bullet).insert(Explosive::new(explosive));
     }
+    bullet
 }
 
 pub fn move_bullets(
     mut commands: Commands,
-    mut queries: ParamSet<(
-        Query<(Entity, &mut Transform, &mut Bullet, &Velocity)>,
-        Query<&Transform, With<Player>>,
-    )>,
+    mut bullet_q: Query<(Entity, &mut Transform, &mut Bullet, &Velocity), Without<Player>>,
+    player_q: Query<&Transform, With<Player>>,
     mut asset_server: Res<AssetServer>,
     time: Res<Time>,
+    bounds: Res<MapBounds>,
 ) {
     let bullet_sound = "sounds/impact.ogg";
     let value = BulletImpactSoundtrackPlayer::new(bullet_sound, &mut asset_server);
-    let player_pos = (
-        queries.p1().single().translation.x,
-        queries.p1().single().translation.y,
-    );
+    let player_pos = player_q
+        .get_single()
+        .map(|t| (t.translation.x, t.translation.y))
+        .unwrap_or((0., 0.));
 
-    for (entity, mut transform, mut bullet, velocity) in queries.p0().iter_mut() {
+    for (entity, mut transform, mut bullet, velocity) in bullet_q.iter_mut() {
         transform.translation.x = bullet.position.0;
         transform.translation.y = bullet.position.1;
+        bullet.previous = bullet.position;
 
         transform.translation.x += velocity.x * time.delta_secs();
         transform.translation.y += velocity.y * time.delta_secs();
 
         bullet.position.0 = transform.translation.x;
         bullet.position.1 = transform.translation.y;
+        bullet.travelled += get_length(bullet.previous, bullet.position);
+
+        // Out of range: it just drops, there's nothing to hit.
+        if bullet.travelled >= bullet.range {
+            commands.entity(entity).despawn();
+            continue;
+        }
 
-        if transform.translation.x.abs() > MAP_WIDTH / 2.0
-            || transform.translation.y.abs() > MAP_HEIGHT / 2.0
-        {
+        if !bounds.contains(transform.translation.truncate()) {
             let dist = get_length(player_pos, bullet.position);
             let mut volume = (dist / 800.) / 10.;
             if volume > 0.4 {
@@ -111,6 +262,95 @@ pub fn move_bullets(
     }
 }
 
+// A bullet stops at the first thing it hits, unless that's a block it can glance off or
+// punch through. Damage to whatever was hit is handled by its own systems, which have to
+// run before this one despawns the bullet.
+// Bullets that have hit something, and whether they blow up when they do.
+type ImpactQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Bullet,
+        &'static mut Velocity,
+        &'static CircleCollider,
+        Has<LocalShot>,
+        Has<Explosive>,
+    ),
+>;
+
+pub fn bullet_impacts(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionStarted>,
+    mut bullet_q: ImpactQuery,
+    block_q: Query<(&BlockCollider, Option<&Destructible>)>,
+    player_q: Query<&Transform, With<Player>>,
+    mut asset_server: Res<AssetServer>,
+    mut block_hits: EventWriter<BlockHit>,
+) {
+    let bullet_sound = "sounds/impact.ogg";
+    let value = BulletImpactSoundtrackPlayer::new(bullet_sound, &mut asset_server);
+    let player_pos = player_q
+        .get_single()
+        .map(|t| (t.translation.x, t.translation.y))
+        .unwrap_or((0., 0.));
+
+    for collision in collisions.read() {
+        let Ok((mut bullet, mut velocity, collider, local, explosive)) =
+            bullet_q.get_mut(collision.a)
+        else {
+            continue;
+        };
+
+        let hit_pos = (collision.point.x, collision.point.y);
+        let volume = ((get_length(player_pos, hit_pos) / 800.) / 10.).min(0.25);
+        BulletImpactSoundtrackPlayer::play_impact_sound(&value, &mut commands, 0.4 - volume);
+
+        let block = block_q.get(collision.b).ok();
+        if let Some((_, Some(destructible))) = block {
+            block_hits.send(BlockHit {
+                index: destructible.index,
+                damage: bullet.current_damage(),
+                position: collision.point,
+                local,
+            });
+        }
+
+        let block = block.map(|(block, _)| block).filter(|_| !explosive);
+        let center = collision.point + collision.normal * collider.radius;
+        let direction = Vec2::new(velocity.x, velocity.y).normalize_or_zero();
+
+        if block.is_some() && bullet.ricochets_left > 0 {
+            // Mirror the velocity in the face it hit.
+            let reflected = Vec2::new(velocity.x, velocity.y).reflect(collision.normal);
+            velocity.x = reflected.x;
+            velocity.y = reflected.y;
+            bullet.velocity = Velocity {
+                x: reflected.x,
+                y: reflected.y,
+            };
+            bullet.ricochets_left -= 1;
+            let position = center + collision.normal * SURFACE_GAP;
+            bullet.position = (position.x, position.y);
+            bullet.previous = bullet.position;
+            continue;
+        }
+
+        let exit = block
+            .and_then(|block| line_through_polygon(center, direction, &block.points))
+            .filter(|&(enter, exit)| exit > 0. && exit - enter.max(0.) <= bullet.penetration)
+            .map(|(_, exit)| exit);
+        if let Some(exit) = exit {
+            let position = center + direction * (exit + collider.radius + SURFACE_GAP);
+            bullet.position = (position.x, position.y);
+            bullet.previous = bullet.position;
+            bullet.damage = (bullet.damage as f32 * PENETRATION_FALLOFF).round() as i32;
+            continue;
+        }
+
+        commands.entity(collision.a).despawn();
+    }
+}
+
 pub fn get_length(p1: (f32, f32), p2: (f32, f32)) -> f32 {
     let dx = p2.0 - p1.0;
     let dy = p2.1 - p1.1;
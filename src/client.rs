@@ -1,98 +1,215 @@
-mod audio;
-mod block;
-mod bullets;
-mod colliders;
-mod enemy;
-mod game;
-mod player;
+use b::{bullets, enemy, game, net, player};
 
 use std::{
-    io::{Read, Write},
-    net::{IpAddr, TcpListener, TcpStream, UdpSocket},
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
 };
 
 use bevy::prelude::*;
-use bevy::{ecs::query::With, state::commands, time, utils::HashMap};
-use bincode;
-use bullets::{move_bullets, shoot, Bullet, Velocity};
-use colliders::colliders_o::{CircleCollider, ColliderType};
+use bevy::{
+    ecs::{query::With, system::SystemParam},
+    utils::HashMap,
+};
+use bullets::{FiredBy, FriendlyFire};
 use enemy::{EnemyBarrel, EnemyPlayer};
 use game::Game;
 
-use crate::audio::audioO::BulletSoundtrackPlayer;
-use player::{player_movement, Barrel, Player};
-use rand::Rng;
-use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
-use crate::enemy::EnemyPlayerHealthBar;
-use crate::player::PlayerHealthBar;
+use b::ai::{AiPlugin, Difficulty};
+use b::death::{
+    apply_respawn, respawn_locally, Dead, RespawnRequested, Respawned, SpawnProtection,
+};
+use b::destruction::{
+    apply_block_health, resolve_block_hits_locally, BlockHealthChanged, BlockHit,
+};
+use b::editor::EditorPlugin;
+use b::health::Health;
+use b::map::{CurrentMap, MapData, MapPlugin, MapSource, MapState, PickupKind};
+use b::movement::{Momentum, MovementState};
+use b::net::{ClientMessage, MessageBuffer, ServerMessage, Welcome};
+use b::pickups::{
+    apply_pickups, boost_damage, grant_pickups_locally, PickupTaken, PickupTouched, PowerUps,
+};
+use b::weapons::{
+    spawn_shot, BarrelShape, Loadout, LoadoutChoice, LoadoutMenu, Shot, Weapon, WeaponFired,
+};
 
-type Players = Arc<Mutex<Vec<Player>>>;
+use b::audio::audioO::BulletSoundtrackPlayer;
+use b::colliders::colliders_o::Kinematic;
+use player::Player;
+use rand::Rng;
 
 fn main() {
-    let stream = TcpStream::connect("127.0.0.1:8080").expect("Failed to connect to server");
-    stream
-        .set_nonblocking(true)
-        .expect("Failed to set non-blocking");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let offline = args.iter().any(|arg| arg == "--offline");
+    let friendly_fire = args.iter().any(|arg| arg == "--friendly-fire");
+    let difficulty = args
+        .iter()
+        .position(|arg| arg == "--difficulty")
+        .and_then(|i| args.get(i + 1))
+        .map(|value| {
+            Difficulty::from_arg(value).expect("--difficulty expects easy, normal or hard")
+        })
+        .unwrap_or(Difficulty::Normal);
+    let loadout = Loadout::from_args(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut source = MapSource::from_args(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let connection = if offline {
+        None
+    } else {
+        let mut stream =
+            TcpStream::connect("127.0.0.1:8080").expect("Failed to connect to server");
+        let id = generate_random_string();
+        stream
+            .write_all(id.as_bytes())
+            .expect("Couldn't send id to server");
+        // The server answers with the map it's running, which replaces any map given
+        // on the command line, and where to start on it.
+        let welcome: Welcome =
+            net::read_message(&mut stream).expect("Couldn't read map from server");
+        source = welcome.map;
+        stream
+            .set_nonblocking(true)
+            .expect("Failed to set non-blocking");
+        Some((stream, id, welcome.spawn))
+    };
 
-    App::new()
-        .insert_resource(TcpStrRes {
+    let mut app = App::new();
+    app.add_plugins(Game)
+        .add_plugins(MapPlugin { source })
+        .add_plugins(EditorPlugin)
+        .insert_resource(LoadoutChoice(loadout))
+        .insert_resource(FriendlyFire(friendly_fire));
+
+    if let Some((stream, id, spawn)) = connection {
+        app.insert_resource(TcpStrRes {
             stream,
+            id,
+            spawn,
+            messages: MessageBuffer::default(),
             connected: true,
         })
-        .add_plugins(Game)
-        .add_systems(Startup, setup)
-        .add_systems(Update, network_update)
-        .add_systems(Update, work_enemy)
-        .run();
+        .init_resource::<RemotePlayers>()
+        .add_systems(
+            Update,
+            (
+                // There's nothing to report until the player has spawned, but the server
+                // still has to be listened to.
+                network_update.run_if(in_state(LoadoutMenu::Done)),
+                work_enemy,
+                send_block_hits,
+                send_respawn_requests,
+                send_loadout,
+                send_pickup_claims,
+            )
+                .run_if(in_state(MapState::Ready)),
+        );
+    } else {
+        app.insert_resource(difficulty)
+            .add_plugins(AiPlugin)
+            .add_systems(
+                Update,
+                (
+                    resolve_block_hits_locally.before(apply_block_health),
+                    respawn_locally.before(apply_respawn),
+                    grant_pickups_locally.before(apply_pickups),
+                ),
+            );
+    }
+
+    // The menu can only be left once the map is up, so the map is there to spawn on.
+    app.add_systems(OnEnter(LoadoutMenu::Done), setup).run();
 }
 
 fn setup(
-    mut stream: ResMut<TcpStrRes>,
+    stream: Option<Res<TcpStrRes>>,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<MapData>>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    choice: Res<LoadoutChoice>,
 ) {
-    let player_id: String = generate_random_string();
-
-    if let Err(e) = stream.stream.write_all(player_id.as_bytes()) {
-        eprintln!("Couldn't send id to server: {}", e);
-    }
+    let player_id = stream
+        .as_ref()
+        .map_or_else(generate_random_string, |stream| stream.id.clone());
+    // Online the server hands out spawn points, so players don't start on top of each other.
+    let position = match stream.as_ref() {
+        Some(stream) => stream.spawn,
+        None => maps
+            .get(&current_map.handle)
+            .and_then(|map| map.spawn_points.first().copied())
+            .unwrap_or((-1000., 0.)),
+    };
 
     let player = Player {
-        id: player_id, //player_id,
-        position: (-1000., 0.),
+        id: player_id.clone(), //player_id,
+        position,
         angle: 0.,
         just_shot: false,
+        weapon: choice.0.primary,
+        shot_seed: 0,
+        shot_angle: 0.,
+        damage_boost: false,
+        sprinting: false,
+        dashing: false,
+        velocity: (0., 0.),
+        heading: (0., 0.),
+        dead: false,
+        protected: false,
         current_health: 1000,
     };
 
-    Player::spawn(&mut commands, &mut meshes, &mut materials, player);
-    EnemyPlayer::spawn(&mut commands, &mut meshes, &mut materials, (1000., 0.));
+    Player::spawn(&mut commands, &mut meshes, &mut materials, player, choice.0);
 }
 #[derive(Resource, Debug)]
 struct TcpStrRes {
     stream: TcpStream,
+    id: String,
+    // Where the server put this client's player when it joined.
+    spawn: (f32, f32),
+    messages: MessageBuffer,
     connected: bool,
 }
 
+// Everything about the local player that goes into its updates.
+type ReportQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Transform,
+        &'static Player,
+        &'static Health,
+        &'static Weapon,
+        &'static PowerUps,
+        &'static MovementState,
+        &'static Momentum,
+        Has<Dead>,
+        Has<SpawnProtection>,
+    ),
+>;
+
 fn network_update(
     windows: Query<&Window>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
     mut stream: ResMut<TcpStrRes>,
-    mut queries: ParamSet<(
-        Query<(&Transform, &Player)>,
-        Query<&Transform, With<Player>>,
-        Query<&mut Transform, With<Barrel>>,
-    )>,
-    mouse: Res<ButtonInput<MouseButton>>,
+    player_q: ReportQuery,
+    mut fired: EventReader<WeaponFired>,
 ) {
-    let mut just_shot: bool = false;
+    // Only shots the weapon actually let out, however the trigger was pulled.
+    let shot = fired.read().last().map(|shot| (shot.angle, shot.seed));
     if !stream.connected {
         return;
     }
 
-    let player_pos = queries.p1().single().translation.truncate();
+    let (transform, player_data, health, weapon, power_ups, movement, momentum, dead, protected) =
+        player_q.single();
+    let player_pos = transform.translation.truncate();
 
     // Get the window and cursor position
     let window = windows.single();
@@ -102,17 +219,10 @@ fn network_update(
         return;
     };
 
-    if mouse.just_pressed(MouseButton::Left) {
-        just_shot = true;
-    }
-
     let (camera, camera_transform) = camera_q.single();
 
     let angle;
 
-    let binding = queries.p0();
-    let (transform, player_data) = binding.single();
-
     if let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position) {
         let direction = world_position - player_pos;
         angle = direction.y.atan2(direction.x);
@@ -124,29 +234,65 @@ fn network_update(
         id: player_data.id.clone(),
         position: (transform.translation.x, transform.translation.y),
         angle,
-        just_shot,
-        current_health: player_data.current_health,
+        just_shot: shot.is_some(),
+        weapon: weapon.kind,
+        shot_seed: shot.map_or(0, |(_, seed)| seed),
+        shot_angle: shot.map_or(angle, |(angle, _)| angle),
+        damage_boost: power_ups.active(PickupKind::DamageBoost),
+        sprinting: movement.sprinting,
+        dashing: movement.dashing,
+        velocity: momentum.velocity.into(),
+        heading: momentum.heading.into(),
+        dead,
+        protected,
+        current_health: health.current,
     };
 
-    if let Ok(msg) = bincode::serialize(&player) {
-        if let Err(e) = stream.stream.write_all(&msg) {
-            eprintln!("Failed to write to stream: {}", e);
-            stream.connected = false;
-        }
+    let msg = net::encode(&ClientMessage::Update(player));
+    if let Err(e) = stream.stream.write_all(&msg) {
+        eprintln!("Failed to write to stream: {}", e);
+        stream.connected = false;
     }
 }
 
+// Everyone else in the game, by player id, each with a body of its own.
+#[derive(Resource, Default)]
+struct RemotePlayers(HashMap<String, Entity>);
+
+type RemoteBodyQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut EnemyPlayer,
+        &'static mut Transform,
+        &'static mut Health,
+        &'static mut MovementState,
+        &'static mut Momentum,
+        &'static mut Visibility,
+        &'static Children,
+    ),
+    Without<Player>,
+>;
+
+#[derive(SystemParam)]
+struct RemoteBodies<'w, 's> {
+    players: ResMut<'w, RemotePlayers>,
+    bodies: RemoteBodyQuery<'w, 's>,
+    barrels: Query<'w, 's, (&'static mut EnemyBarrel, &'static mut BarrelShape)>,
+    local: Query<'w, 's, &'static mut Transform, With<Player>>,
+}
+
+// What the server says happened to the world rather than to any one player.
+#[derive(SystemParam)]
+struct ServerEvents<'w> {
+    block_changes: EventWriter<'w, BlockHealthChanged>,
+    respawns: EventWriter<'w, Respawned>,
+    pickups_taken: EventWriter<'w, PickupTaken>,
+}
+
 fn work_enemy(
-    // mut commands: Commands,
-    // mut meshes: ResMut<Assets<Mesh>>,
-    // mut materials: ResMut<Assets<ColorMaterial>>,
-    mut queries: ParamSet<(
-        Query<&mut Transform, With<EnemyBarrel>>,
-        Query<(&mut Transform, &EnemyPlayer)>,
-        Query<&Player>,
-        Query<Entity, With<EnemyPlayerHealthBar>>,
-        Query<Entity, With<EnemyPlayer>>,
-    )>,
+    mut remote: RemoteBodies,
+    mut events: ServerEvents,
     asset_server: Res<AssetServer>,
     mut stream: ResMut<TcpStrRes>,
     mut commands: Commands,
@@ -154,147 +300,194 @@ fn work_enemy(
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
     let mut buffer = [0; 1024];
-    match stream.stream.read(&mut buffer) {
-        Ok(0) => {
-            println!("No enemy data yet");
+    loop {
+        match stream.stream.read(&mut buffer) {
+            Ok(0) => {
+                println!("No enemy data yet");
+                break;
+            }
+            Ok(msg) => stream.messages.extend(&buffer[..msg]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                eprintln!("Error reading from stream: {}", e);
+                break;
+            }
         }
-        Ok(msg) => {
-            if let Ok(data) = bincode::deserialize::<Player>(&buffer[..msg]) {
-                let player_id = if let Ok(player) = queries.p2().get_single() {
-                    player.id.clone()
-                } else {
-                    return;
-                };
-
-                let mut binding = queries.p0();
-                let mut barrel_transform = binding.single_mut();
-                let angle = data.angle;
-                barrel_transform.rotation = Quat::from_rotation_z(angle);
-                barrel_transform.translation = Vec3::new(angle.cos() * 70., angle.sin() * 70., 1.0);
-
-                if data.just_shot {
-                    let bullet_sound = "sounds/gunshot.ogg";
-                    let bsp = BulletSoundtrackPlayer::new(bullet_sound, asset_server);
-                    BulletSoundtrackPlayer::play_bullet_sound(bsp, &mut commands);
-                    let bullet_position = barrel_transform.translation
-                        + Vec3::new(angle.cos() * 140.0, angle.sin() * 140.0, 0.0);
-                    let velocity = Vec2::new(angle.cos(), angle.sin()) * 3500.0;
-                    let bullet = Bullet {
-                        velocity: Velocity {
-                            x: velocity.x,
-                            y: velocity.y,
-                        },
-                        position: (
-                            queries.p1().single().0.translation.x + angle.cos() * 80.,
-                            queries.p1().single().0.translation.y + angle.sin() * 80.,
-                        ),
-                    };
-                    commands.spawn((
-                        bullet,
-                        CircleCollider {
-                            collider_type: ColliderType::Bullet,
-                            radius: 10.0,
-                        },
-                        Mesh2d(meshes.add(Circle::new(10.0))),
-                        MeshMaterial2d(materials.add(ColorMaterial::from(Color::WHITE))),
-                        Transform::from_translation(bullet_position),
-                        Velocity {
-                            x: velocity.x,
-                            y: velocity.y,
-                        },
-                    ));
-                }
+    }
 
-                let enemy_player = queries.p4().single();
-
-                let entity = queries.p3().single_mut();
-
-                match data.current_health {
-                    800 => {
-                        commands.entity(entity).despawn();
-                        commands
-                            .spawn((
-                                EnemyPlayerHealthBar {
-                                    current_health: 800.0,
-                                },
-                                Mesh2d(meshes.add(Rectangle::new(104., 25.))),
-                                MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                                Transform::from_xyz(0., -110., 5.0),
-                            ))
-                            .set_parent(enemy_player);
-                    }
-                    600 => {
-                        commands.entity(entity).despawn();
-
-                        commands
-                            .spawn((
-                                EnemyPlayerHealthBar {
-                                    current_health: 600.0,
-                                },
-                                Mesh2d(meshes.add(Rectangle::new(78., 25.))),
-                                MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                                Transform::from_xyz(0., -110., 5.0),
-                            ))
-                            .set_parent(enemy_player);
-                    }
-                    400 => {
-                        commands.entity(entity).despawn();
-
-                        commands
-                            .spawn((
-                                EnemyPlayerHealthBar {
-                                    current_health: 400.0,
-                                },
-                                Mesh2d(meshes.add(Rectangle::new(52., 25.))),
-                                MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                                Transform::from_xyz(0., -110., 5.0),
-                            ))
-                            .set_parent(enemy_player);
-                    }
-                    200 => {
-                        commands.entity(entity).despawn();
-
-                        commands
-                            .spawn((
-                                EnemyPlayerHealthBar {
-                                    current_health: 200.0,
-                                },
-                                Mesh2d(meshes.add(Rectangle::new(26., 25.))),
-                                MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                                Transform::from_xyz(0., -110., 5.0),
-                            ))
-                            .set_parent(enemy_player);
-                    }
-                    0 => {
-                        commands.entity(entity).despawn();
-                    }
-                    _ => {
-                        eprintln!("Unhandled player health value: {}", data.current_health);
-                    }
+    let dropped = stream.messages.dropped;
+    for message in stream.messages.drain::<ServerMessage>() {
+        let data = match message {
+            ServerMessage::Update(data) => data,
+            ServerMessage::BlockHealth { index, health } => {
+                events
+                    .block_changes
+                    .send(BlockHealthChanged { index, health });
+                continue;
+            }
+            ServerMessage::Respawn { position } => {
+                events.respawns.send(Respawned { position });
+                continue;
+            }
+            ServerMessage::PickupTaken {
+                index,
+                by,
+                respawn_in,
+            } => {
+                events.pickups_taken.send(PickupTaken {
+                    index,
+                    local: by == stream.id,
+                    respawn_in,
+                });
+                continue;
+            }
+            ServerMessage::Correction { position } => {
+                if let Ok(mut transform) = remote.local.get_single_mut() {
+                    transform.translation.x = position.0;
+                    transform.translation.y = position.1;
                 }
+                continue;
+            }
+        };
 
-                if data.id != player_id {
-                    for (mut transform, _) in queries.p1().iter_mut() {
-                        println!(
-                            "{} position: ({}, {}), angle: {}, health: {}",
-                            data.id,
-                            data.position.0,
-                            data.position.1,
-                            data.angle,
-                            data.current_health,
-                        );
-
-                        transform.translation.x = data.position.0;
-                        transform.translation.y = data.position.1;
-                    }
-                }
+        if data.id == stream.id {
+            continue;
+        }
+
+        // Someone new gets a body where they said they are. Its position comes from the
+        // server, so local body collisions leave it alone.
+        let enemy = *remote.players.0.entry(data.id.clone()).or_insert_with(|| {
+            let enemy =
+                EnemyPlayer::spawn(&mut commands, &mut meshes, &mut materials, data.position);
+            commands.entity(enemy).insert(Kinematic);
+            enemy
+        });
+
+        if data.just_shot {
+            let bullet_sound = "sounds/gunshot.ogg";
+            let bsp = BulletSoundtrackPlayer::new(bullet_sound, Res::clone(&asset_server));
+            BulletSoundtrackPlayer::play_bullet_sound(bsp, &mut commands);
+            let fired_by = FiredBy {
+                entity: enemy,
+                owner: data.id.clone(),
+            };
+            let mut stats = data.weapon.stats();
+            if data.damage_boost {
+                boost_damage(&mut stats);
+            }
+            let shot = Shot {
+                fired_by,
+                origin: data.position.into(),
+                angle: data.shot_angle,
+                seed: data.shot_seed,
+            };
+            spawn_shot(&mut commands, &mut meshes, &mut materials, shot, &stats);
+        }
+
+        // A body spawned for this update only shows up once the commands are applied.
+        let Ok((
+            mut enemy_player,
+            mut transform,
+            mut health,
+            mut movement,
+            mut momentum,
+            mut visibility,
+            children,
+        )) = remote.bodies.get_mut(enemy)
+        else {
+            continue;
+        };
+
+        for &child in children.iter() {
+            if let Ok((mut enemy_barrel, mut barrel_shape)) = remote.barrels.get_mut(child) {
+                enemy_barrel.angle = data.angle;
+                barrel_shape.set_if_neq(BarrelShape(data.weapon));
+            }
+        }
+
+        // Whoever owns a player decides its health; this just shows it.
+        if health.current != data.current_health {
+            health.current = data.current_health;
+        }
+
+        movement.sprinting = data.sprinting;
+        movement.dashing = data.dashing;
+        momentum.velocity = data.velocity.into();
+        momentum.heading = data.heading.into();
+
+        if enemy_player.dead != data.dead {
+            enemy_player.dead = data.dead;
+            if data.dead {
+                commands.entity(enemy).insert(Dead::new(None));
             } else {
-                eprintln!("Failed to deserialize player data");
+                commands.entity(enemy).remove::<Dead>();
+                transform.scale = Vec3::ONE;
+                transform.rotation = Quat::IDENTITY;
             }
         }
-        Err(e) => {
-            eprintln!("Error reading from stream: {}", e);
+        if enemy_player.protected != data.protected {
+            enemy_player.protected = data.protected;
+            if data.protected {
+                commands.entity(enemy).insert(SpawnProtection::default());
+            } else {
+                commands.entity(enemy).remove::<SpawnProtection>();
+                *visibility = Visibility::Inherited;
+            }
         }
+
+        transform.translation.x = data.position.0;
+        transform.translation.y = data.position.1;
+    }
+    if stream.messages.dropped > dropped {
+        eprintln!("Failed to deserialize player data");
+    }
+}
+
+// Reports the local player's hits on destructible blocks; the server decides the
+// damage and tells everyone.
+fn send_block_hits(mut hits: EventReader<BlockHit>, mut stream: ResMut<TcpStrRes>) {
+    for hit in hits.read().filter(|hit| hit.local) {
+        let message = ClientMessage::DamageBlock {
+            index: hit.index,
+            damage: hit.damage,
+        };
+        if let Err(e) = stream.stream.write_all(&net::encode(&message)) {
+            eprintln!("Failed to write to stream: {}", e);
+        }
+    }
+}
+
+// Online, the server picks where a dead player comes back.
+fn send_respawn_requests(
+    mut requests: EventReader<RespawnRequested>,
+    mut stream: ResMut<TcpStrRes>,
+) {
+    for _ in requests.read() {
+        if let Err(e) = stream.stream.write_all(&net::encode(&ClientMessage::Respawn)) {
+            eprintln!("Failed to write to stream: {}", e);
+        }
+    }
+}
+
+// Online, the server decides who gets a pickup when several players reach it at once.
+fn send_pickup_claims(mut touched: EventReader<PickupTouched>, mut stream: ResMut<TcpStrRes>) {
+    for touch in touched.read() {
+        let message = ClientMessage::ClaimPickup { index: touch.index };
+        if let Err(e) = stream.stream.write_all(&net::encode(&message)) {
+            eprintln!("Failed to write to stream: {}", e);
+        }
+    }
+}
+
+// Tells the server what the player will spawn with, once on joining and again whenever
+// it's changed.
+fn send_loadout(choice: Res<LoadoutChoice>, mut stream: ResMut<TcpStrRes>) {
+    if !choice.is_changed() {
+        return;
+    }
+    let message = ClientMessage::Loadout(choice.0);
+    if let Err(e) = stream.stream.write_all(&net::encode(&message)) {
+        eprintln!("Failed to write to stream: {}", e);
     }
 }
 
@@ -1,12 +1,13 @@
 pub mod colliders_o {
-    use bevy::prelude::*;
+    use bevy::{prelude::*, utils::HashSet};
 
     use crate::{
-        audio::audioO::BulletImpactSoundtrackPlayer, bullets, colliders, enemy, player::Player,
+        broadphase::{BlockBroadphase, BodyBroadphase},
+        bullets,
+        movement::Momentum,
     };
 
     use bullets::*;
-    use colliders::*;
 
     #[derive(Component)]
     #[allow(warnings)]
@@ -19,9 +20,31 @@ pub mod colliders_o {
     #[allow(warnings)]
     pub struct BlockCollider {
         pub collider_type: ColliderType,
-        pub points: ((f32, f32), (f32, f32), (f32, f32), (f32, f32)),
-        pub width: f32,
-        pub height: f32,
+        // Convex polygon in world space.
+        pub points: Vec<Vec2>,
+        // Bounding box, for cheap rejection before the SAT tests.
+        pub min: Vec2,
+        pub max: Vec2,
+    }
+
+    impl BlockCollider {
+        pub fn new(collider_type: ColliderType, points: Vec<Vec2>) -> Self {
+            let min = points.iter().copied().fold(Vec2::MAX, Vec2::min);
+            let max = points.iter().copied().fold(Vec2::MIN, Vec2::max);
+            BlockCollider {
+                collider_type,
+                points,
+                min,
+                max,
+            }
+        }
+
+        pub fn near(&self, position: Vec2, radius: f32) -> bool {
+            position.x + radius >= self.min.x
+                && position.x - radius <= self.max.x
+                && position.y + radius >= self.min.y
+                && position.y - radius <= self.max.y
+        }
     }
 
     #[allow(warnings)]
@@ -32,230 +55,643 @@ pub mod colliders_o {
         Bullet,
     }
 
+    // One bit per kind of collider. Two colliders only interact if each one's filters
+    // include one of the other's memberships.
+    pub const LAYER_PLAYER: u32 = 1 << 0;
+    pub const LAYER_ENEMY: u32 = 1 << 1;
+    pub const LAYER_BLOCK: u32 = 1 << 2;
+    pub const LAYER_BULLET: u32 = 1 << 3;
+
+    // Overrides the layers a collider gets from its `ColliderType`.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct CollisionLayers {
+        pub memberships: u32,
+        pub filters: u32,
+    }
+
+    impl CollisionLayers {
+        pub const fn new(memberships: u32, filters: u32) -> Self {
+            CollisionLayers {
+                memberships,
+                filters,
+            }
+        }
+
+        pub fn interacts_with(&self, other: &CollisionLayers) -> bool {
+            self.filters & other.memberships != 0 && other.filters & self.memberships != 0
+        }
+
+        // The entity's own layers if it has any, otherwise its type's defaults.
+        pub fn of(collider_type: &ColliderType, layers: Option<&CollisionLayers>) -> Self {
+            layers
+                .copied()
+                .unwrap_or_else(|| collider_type.default_layers())
+        }
+    }
+
+    impl ColliderType {
+        pub fn default_layers(&self) -> CollisionLayers {
+            match self {
+                ColliderType::Player => CollisionLayers::new(
+                    LAYER_PLAYER,
+                    LAYER_PLAYER | LAYER_ENEMY | LAYER_BLOCK | LAYER_BULLET,
+                ),
+                ColliderType::Enemy => CollisionLayers::new(
+                    LAYER_ENEMY,
+                    LAYER_PLAYER | LAYER_ENEMY | LAYER_BLOCK | LAYER_BULLET,
+                ),
+                ColliderType::Block => {
+                    CollisionLayers::new(LAYER_BLOCK, LAYER_PLAYER | LAYER_ENEMY | LAYER_BULLET)
+                }
+                ColliderType::Bullet => {
+                    CollisionLayers::new(LAYER_BULLET, LAYER_PLAYER | LAYER_ENEMY | LAYER_BLOCK)
+                }
+            }
+        }
+    }
+
+    // Two colliders have just started touching. `a` is the one that moved into `b`,
+    // `normal` points from `b` towards `a`, `depth` is how far they overlap along it and
+    // `point` is where they met. Gameplay reacts to these rather than to the colliders.
+    #[derive(Event, Clone, Copy, Debug)]
+    pub struct CollisionStarted {
+        pub a: Entity,
+        pub b: Entity,
+        pub normal: Vec2,
+        pub depth: f32,
+        pub point: Vec2,
+    }
+
+    // Pairs touching as of the last frame, so a contact that lasts several frames is only
+    // reported once.
+    #[derive(Resource, Default)]
+    pub struct Contacts {
+        blocks: HashSet<(Entity, Entity)>,
+        bodies: HashSet<(Entity, Entity)>,
+    }
+
+    // A body whose position comes from elsewhere, like a remote player's from the server.
+    // Body collisions never move it; whatever it bumps into takes the whole push.
+    #[derive(Component)]
+    pub struct Kinematic;
+
     pub struct CollidersPlugin;
 
     impl Plugin for CollidersPlugin {
         fn build(&self, app: &mut App) {
-            app.add_systems(Update, detect_block_and_circle_collision)
-                .add_systems(Update, detect_block_and_bullet_collision);
+            app.add_event::<CollisionStarted>()
+                .init_resource::<Contacts>();
         }
     }
 
+    // Bodies that walls push out, stopping them where they move with momentum.
+    type WalledQuery<'w, 's> = Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static mut Transform,
+            &'static CircleCollider,
+            Option<&'static CollisionLayers>,
+            Option<&'static mut Momentum>,
+        ),
+        Without<Bullet>,
+    >;
+
+    // Bodies that push each other apart, unless they're `Kinematic`.
+    type BodyQuery<'w, 's> = Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static mut Transform,
+            &'static CircleCollider,
+            Option<&'static CollisionLayers>,
+            Has<Kinematic>,
+        ),
+        Without<Bullet>,
+    >;
+
     pub fn detect_block_and_circle_collision(
-        mut player_q: Query<&mut Transform, (With<CircleCollider>, Without<Bullet>)>,
-        block_q: Query<(Entity, &BlockCollider)>,
+        mut circle_q: WalledQuery,
+        block_q: Query<(&BlockCollider, Option<&CollisionLayers>)>,
+        broadphase: Res<BlockBroadphase>,
+        mut contacts: ResMut<Contacts>,
+        mut collisions: EventWriter<CollisionStarted>,
     ) {
-        let mut player = player_q.single_mut();
-        let player_x = player.translation.x;
-        let player_y = player.translation.y;
-        let player_offset = 70.0;
-        let buffer = 2.0; // Small buffer to prevent sticking
-
-        for (_entity, block) in block_q.iter() {
-            let block_x_min = block.points.0 .0;
-            let block_x_max = block.points.2 .0;
-            let block_y_max = block.points.0 .1;
-            let block_y_min = block.points.2 .1;
-
-            // Early exit if not near block
-            if player_x + player_offset < block_x_min - player_offset
-                || player_x - player_offset > block_x_max + player_offset
-                || player_y + player_offset < block_y_min - player_offset
-                || player_y - player_offset > block_y_max + player_offset
-            {
-                continue;
-            }
-
-            let closest_x = player_x.clamp(block_x_min, block_x_max);
-            let closest_y = player_y.clamp(block_y_min, block_y_max);
-
-            let dx = player_x - closest_x;
-            let dy = player_y - closest_y;
-            let distance = (dx * dx + dy * dy).sqrt();
+        let mut touching = HashSet::new();
+        let mut candidates = Vec::new();
+        for (entity, mut player, collider, layers, mut momentum) in circle_q.iter_mut() {
+            let layers = CollisionLayers::of(&collider.collider_type, layers);
+            let buffer = 2.0; // Small buffer to prevent sticking
+            let radius = collider.radius + buffer;
+
+            broadphase.query_circle(player.translation.truncate(), radius, &mut candidates);
+            for &block_entity in candidates.iter() {
+                let Ok((block, block_layers)) = block_q.get(block_entity) else {
+                    continue;
+                };
+                let position = player.translation.truncate();
+                if !block.near(position, radius)
+                    || !layers
+                        .interacts_with(&CollisionLayers::of(&block.collider_type, block_layers))
+                {
+                    continue;
+                }
 
-            if distance < player_offset + buffer {
-                if distance > 0.1 {
-                    let target_distance = player_offset + buffer;
-                    let scale = target_distance / distance;
+                if let Some(push) = circle_polygon_push(position, radius, &block.points) {
+                    player.translation.x += push.x;
+                    player.translation.y += push.y;
+                    let normal = push.normalize_or_zero();
+                    if let Some(momentum) = momentum.as_mut() {
+                        momentum.stop_against(normal);
+                    }
 
-                    player.translation.x = closest_x + dx * scale;
-                    player.translation.y = closest_y + dy * scale;
-                } else {
-                    player.translation.x += player_offset;
+                    let pair = (entity, block_entity);
+                    if !contacts.blocks.contains(&pair) {
+                        collisions.send(CollisionStarted {
+                            a: entity,
+                            b: block_entity,
+                            normal,
+                            depth: push.length(),
+                            point: position + push - normal * radius,
+                        });
+                    }
+                    touching.insert(pair);
                 }
             }
         }
+        contacts.blocks = touching;
     }
 
-    // pub fn detect_block_and_circle_collision(
-    //     mut player_q: Query<&mut Transform, (With<CircleCollider>, Without<Bullet>)>,
-    //     block_q: Query<(Entity, &BlockCollider)>,
-    // ) {
-    //     let mut player = player_q.single_mut();
-
-    //     let player_x = player.translation.x;
-    //     let player_y = player.translation.y;
-
-    //     let player_offset = 70.0;
-
-    //     for (_entity, block) in block_q.iter() {
-    //         let block_x_min = block.points.0 .0;
-    //         let block_x_max = block.points.2 .0;
-    //         let block_y_max = block.points.0 .1;
-    //         let block_y_min = block.points.2 .1;
-
-    //         if (player_x + player_offset > block_x_min
-    //             && player_x + player_offset < block_x_max
-    //             && player_y > block_y_min
-    //             && player_y < block_y_max)
-    //             || (player_x - player_offset > block_x_min
-    //                 && player_x - player_offset < block_x_max
-    //                 && player_y > block_y_min
-    //                 && player_y < block_y_max)
-    //             || (player_x > block_x_min
-    //                 && player_x < block_x_max
-    //                 && player_y - player_offset > block_y_min
-    //                 && player_y - player_offset < block_y_max)
-    //             || (player_x > block_x_min
-    //                 && player_x < block_x_max
-    //                 && player_y + player_offset > block_y_min
-    //                 && player_y + player_offset < block_y_max)
-    //         {
-    //             if player_x + player_offset > block_x_min && player_x < block_x_min {
-    //                 player.translation.x = block_x_min - player_offset;
-    //             } else if player_x - player_offset < block_x_max && player_x > block_x_max {
-    //                 player.translation.x = block_x_max + player_offset;
-    //             }
-
-    //             if player_y + player_offset > block_y_min && player_y < block_y_min {
-    //                 player.translation.y = block_y_min - player_offset;
-    //             } else if player_y - player_offset < block_y_max && player_y > block_y_max {
-    //                 player.translation.y = block_y_max + player_offset;
-    //             }
-
-    //             println!(
-    //                 "Collision resolved: Player moved to ({}, {})",
-    //                 player.translation.x, player.translation.y
-    //             );
-    //         }
-    //     }
-    // }
-
-    pub fn detect_block_and_bullet_collision(
-        mut commands: Commands,
-        mut queries: ParamSet<(
-            Query<(Entity, &mut Transform), (With<CircleCollider>, With<Bullet>, Without<Player>)>,
-            Query<&Transform, With<Player>>,
-        )>,
-        mut asset_server: Res<AssetServer>,
-        block_q: Query<(Entity, &BlockCollider)>,
+    // Keeps player bodies from overlapping. Each side of a pair takes half the push,
+    // unless one of them is `Kinematic`.
+    pub fn detect_circle_and_circle_collision(
+        mut circle_q: BodyQuery,
+        broadphase: Res<BodyBroadphase>,
+        mut contacts: ResMut<Contacts>,
+        mut collisions: EventWriter<CollisionStarted>,
     ) {
-        let bullet_sound = "sounds/impact.ogg";
-        let value = BulletImpactSoundtrackPlayer::new(bullet_sound, &mut asset_server);
+        let mut touching = HashSet::new();
+        let mut candidates = Vec::new();
+        let bodies: Vec<Entity> = circle_q.iter().map(|(entity, ..)| entity).collect();
+        for a in bodies {
+            let Ok((_, a_transform, a_collider, ..)) = circle_q.get(a) else {
+                continue;
+            };
+            broadphase.query_circle(
+                a_transform.translation.truncate(),
+                a_collider.radius,
+                &mut candidates,
+            );
+            // Each pair once, with the same entity first every tick.
+            for &b in candidates.iter().filter(|&&b| b > a) {
+                let Ok([a_body, b_body]) = circle_q.get_many_mut([a, b]) else {
+                    continue;
+                };
+                let (_, mut a_transform, a_collider, a_layers, a_kinematic) = a_body;
+                let (_, mut b_transform, b_collider, b_layers, b_kinematic) = b_body;
+                let a_layers = CollisionLayers::of(&a_collider.collider_type, a_layers);
+                let b_layers = CollisionLayers::of(&b_collider.collider_type, b_layers);
+                if !a_layers.interacts_with(&b_layers) {
+                    continue;
+                }
 
-        let player_pos = (
-            queries.p1().single().translation.x,
-            queries.p1().single().translation.y,
-        );
+                let a_pos = a_transform.translation.truncate();
+                let b_pos = b_transform.translation.truncate();
+                let Some(push) =
+                    circle_circle_push(a_pos, a_collider.radius, b_pos, b_collider.radius)
+                else {
+                    continue;
+                };
+
+                let (a_share, b_share) = match (a_kinematic, b_kinematic) {
+                    (false, false) => (0.5, 0.5),
+                    (false, true) => (1., 0.),
+                    (true, false) => (0., 1.),
+                    (true, true) => (0., 0.),
+                };
+                a_transform.translation += (push * a_share).extend(0.);
+                b_transform.translation -= (push * b_share).extend(0.);
+
+                if !contacts.bodies.contains(&(a, b)) {
+                    let normal = push.normalize_or_zero();
+                    collisions.send(CollisionStarted {
+                        a,
+                        b,
+                        normal,
+                        depth: push.length(),
+                        point: b_pos + normal * b_collider.radius,
+                    });
+                }
+                touching.insert((a, b));
+            }
+        }
+        contacts.bodies = touching;
+    }
+
+    // Smallest push that moves circle a out of circle b, or None if they don't overlap.
+    pub fn circle_circle_push(a: Vec2, radius_a: f32, b: Vec2, radius_b: f32) -> Option<Vec2> {
+        let offset = a - b;
+        let depth = radius_a + radius_b - offset.length();
+        if depth <= 0. {
+            return None;
+        }
+        // Exactly on top of each other, so any direction will do.
+        Some(offset.try_normalize().unwrap_or(Vec2::X) * depth)
+    }
 
-        for (entity, mut _bullet) in queries.p0().iter_mut() {
-            let bullet_x = _bullet.translation.x;
-            let bullet_y = _bullet.translation.y;
+    fn project(points: &[Vec2], axis: Vec2) -> (f32, f32) {
+        points
+            .iter()
+            .map(|point| point.dot(axis))
+            .fold((f32::MAX, f32::MIN), |(min, max), d| {
+                (min.min(d), max.max(d))
+            })
+    }
 
-            let bullet_radius = 5.0;
+    fn edge_normals(points: &[Vec2]) -> impl Iterator<Item = Vec2> + '_ {
+        points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .filter_map(|(&a, &b)| (b - a).perp().try_normalize())
+    }
 
-            for (_block_entity, block) in block_q.iter() {
-                let block_x_min = block.points.0 .0;
-                let block_x_max = block.points.2 .0;
-                let block_y_max = block.points.0 .1;
-                let block_y_min = block.points.2 .1;
+    // Separating axis test of a circle against a convex polygon. Returns the smallest
+    // push that moves the circle out of the polygon, or None if they don't overlap.
+    pub fn circle_polygon_push(center: Vec2, radius: f32, points: &[Vec2]) -> Option<Vec2> {
+        let closest_vertex = points.iter().copied().min_by(|a, b| {
+            a.distance_squared(center)
+                .total_cmp(&b.distance_squared(center))
+        })?;
+
+        let mut best: Option<Vec2> = None;
+        let vertex_axis = (center - closest_vertex).try_normalize();
+        for axis in edge_normals(points).chain(vertex_axis) {
+            let (min, max) = project(points, axis);
+            let c = center.dot(axis);
+            // How far the circle has to move along +axis or -axis to clear the polygon.
+            let forward = max - (c - radius);
+            let backward = (c + radius) - min;
+            if forward <= 0. || backward <= 0. {
+                return None;
+            }
+            let push = if forward < backward {
+                axis * forward
+            } else {
+                -axis * backward
+            };
+            if best.is_none_or(|best| push.length_squared() < best.length_squared()) {
+                best = Some(push);
+            }
+        }
+        best
+    }
 
-                if bullet_x + bullet_radius > block_x_min
-                    && bullet_x - bullet_radius < block_x_max
-                    && bullet_y + bullet_radius > block_y_min
-                    && bullet_y - bullet_radius < block_y_max
-                {
-                    let dist = get_length(player_pos, (bullet_x, bullet_y));
-                    let mut volume = (dist / 800.) / 10.;
+    // Separating axis test of the segment a -> b against a convex polygon.
+    pub fn segment_hits_polygon(a: Vec2, b: Vec2, points: &[Vec2]) -> bool {
+        let segment = [a, b];
+        let segment_axis = (b - a).perp().try_normalize();
+        edge_normals(points).chain(segment_axis).all(|axis| {
+            let (poly_min, poly_max) = project(points, axis);
+            let (seg_min, seg_max) = project(&segment, axis);
+            seg_max >= poly_min && seg_min <= poly_max
+        })
+    }
 
-                    if volume > 0.4 {
-                        volume = 0.25;
-                    }
+    // Expects the points counter-clockwise, as blocks store them.
+    pub fn polygon_contains(points: &[Vec2], position: Vec2) -> bool {
+        points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .all(|(&a, &b)| (b - a).perp_dot(position - a) >= 0.)
+    }
 
-                    BulletImpactSoundtrackPlayer::play_impact_sound(
-                        &value,
-                        &mut commands,
-                        0.4 - volume,
-                    );
-                    commands.entity(entity).despawn();
-                    break;
+    // Where a line through `origin` along the unit `direction` goes into and comes out
+    // of the polygon, as distances from `origin`, or None if it misses. Expects the
+    // points counter-clockwise, as blocks store them.
+    pub fn line_through_polygon(
+        origin: Vec2,
+        direction: Vec2,
+        points: &[Vec2],
+    ) -> Option<(f32, f32)> {
+        let (mut enter, mut exit) = (f32::NEG_INFINITY, f32::INFINITY);
+        for (&a, &b) in points.iter().zip(points.iter().cycle().skip(1)) {
+            let inward = (b - a).perp();
+            let offset = inward.dot(origin - a);
+            let approach = inward.dot(direction);
+            if approach == 0. {
+                if offset < 0. {
+                    return None;
                 }
+            } else if approach > 0. {
+                enter = enter.max(-offset / approach);
+            } else {
+                exit = exit.min(-offset / approach);
             }
         }
+        (!points.is_empty() && enter <= exit).then_some((enter, exit))
     }
 
-    pub fn detect_player_and_bullet_collision(
-        mut queries: ParamSet<(
-            Query<&Transform, (With<CircleCollider>, With<Player>, Without<Bullet>)>,
-            Query<
-                (Entity, &Transform, &Bullet),
-                (With<CircleCollider>, With<Bullet>, Without<Player>),
-            >,
-        )>,
-        mut asset_server: Res<AssetServer>,
-        mut commands: Commands,
-        mut player_q: Query<&mut Player>,
-    ) {
-        // Get player position
-        let player_pos = match queries.p0().get_single() {
-            Ok(transform) => transform.translation,
-            Err(_) => {
-                eprintln!("Player entity not found");
-                return;
-            }
-        };
+    pub fn segment_hits_block(a: Vec2, b: Vec2, block: &BlockCollider) -> bool {
+        segment_hits_polygon(a, b, &block.points)
+    }
+
+    // Earliest fraction along from -> from + delta at which the point is inside the
+    // circle, or None if it never gets there. Moving away never counts, even from inside,
+    // so a shot doesn't hit the circle it was fired from.
+    pub fn ray_circle(from: Vec2, delta: Vec2, center: Vec2, radius: f32) -> Option<f32> {
+        let offset = from - center;
+        let b = offset.dot(delta);
+        if b >= 0. {
+            return None;
+        }
+        let c = offset.length_squared() - radius * radius;
+        if c <= 0. {
+            return Some(0.);
+        }
+        let a = delta.length_squared();
+        let discriminant = b * b - a * c;
+        if discriminant < 0. {
+            return None;
+        }
+        let t = (-b - discriminant.sqrt()) / a;
+        (t <= 1.).then_some(t)
+    }
+
+    // Earliest fraction along from -> to at which a circle of `radius` touches the convex
+    // polygon, and the polygon's normal there. The polygon grown by `radius` is its edges
+    // pushed outwards plus a circle on every corner, so this is a ray cast against those.
+    pub fn swept_circle_polygon(
+        from: Vec2,
+        to: Vec2,
+        radius: f32,
+        points: &[Vec2],
+    ) -> Option<(f32, Vec2)> {
+        if points.is_empty() {
+            return None;
+        }
+        if let Some(push) = circle_polygon_push(from, radius, points) {
+            return Some((0., push.normalize_or_zero()));
+        }
 
-        // Get player component
-        let mut player = match player_q.get_single_mut() {
-            Ok(player) => player,
-            Err(e) => {
-                eprintln!("Player component not found: {}", e);
-                return;
+        let delta = to - from;
+        let centroid = points.iter().copied().sum::<Vec2>() / points.len() as f32;
+        let mut first: Option<(f32, Vec2)> = None;
+        let mut hit = |t: f32, normal: Vec2| {
+            if (0. ..=1.).contains(&t) && first.is_none_or(|(first, _)| t < first) {
+                first = Some((t, normal));
             }
         };
 
-        let player_x = player_pos.x;
-        let player_y = player_pos.y;
-
-        // Collect entities to despawn
-        let mut entities_to_despawn = Vec::new();
+        for (&a, &b) in points.iter().zip(points.iter().cycle().skip(1)) {
+            if let Some(t) = ray_circle(from, delta, a, radius) {
+                hit(t, (from + delta * t - a).normalize_or_zero());
+            }
 
-        // Check for collisions
-        for (entity, transform, _bullet) in queries.p1().iter_mut() {
-            let bullet_x = transform.translation.x;
-            let bullet_y = transform.translation.y;
+            let Some(edge) = (b - a).try_normalize() else {
+                continue;
+            };
+            let mut normal = edge.perp();
+            if normal.dot(a - centroid) < 0. {
+                normal = -normal;
+            }
+            let approach = delta.dot(normal);
+            if approach >= 0. {
+                continue;
+            }
+            let t = (a + normal * radius - from).dot(normal) / approach;
+            let along = (from + delta * t - a).dot(edge);
+            if along >= 0. && along <= a.distance(b) {
+                hit(t, normal);
+            }
+        }
+        first
+    }
 
-            let dx = bullet_x - player_x;
-            let dy = bullet_y - player_y;
-            let distance = (dx * dx + dy * dy).sqrt();
-            let collision_distance = 70.0;
+    // Sweeps every bullet over its last step and reports the first collider it reached,
+    // so fast bullets can't skip over thin walls or players at low frame rates.
+    pub fn detect_bullet_collisions(
+        bullet_q: Query<(Entity, &Bullet, &CircleCollider, Option<&CollisionLayers>)>,
+        circle_q: Query<(&Transform, &CircleCollider, Option<&CollisionLayers>), Without<Bullet>>,
+        block_q: Query<(&BlockCollider, Option<&CollisionLayers>)>,
+        broadphase: Res<BlockBroadphase>,
+        bodies: Res<BodyBroadphase>,
+        mut collisions: EventWriter<CollisionStarted>,
+    ) {
+        let mut candidates = Vec::new();
+        for (entity, bullet, collider, layers) in bullet_q.iter() {
+            let layers = CollisionLayers::of(&collider.collider_type, layers);
+            let radius = collider.radius;
+            let from = Vec2::from(bullet.previous);
+            let to = Vec2::from(bullet.position);
+
+            let mut first: Option<(f32, CollisionStarted)> = None;
+            let mut hit = |t: f32, other: Entity, normal: Vec2, depth: f32| {
+                if first.is_none_or(|(first, _)| t < first) {
+                    let position = from.lerp(to, t);
+                    first = Some((
+                        t,
+                        CollisionStarted {
+                            a: entity,
+                            b: other,
+                            normal,
+                            depth: depth.max(0.),
+                            point: position - normal * radius,
+                        },
+                    ));
+                }
+            };
+
+            broadphase.query(
+                from.min(to) - Vec2::splat(radius),
+                from.max(to) + Vec2::splat(radius),
+                &mut candidates,
+            );
+            for &block_entity in candidates.iter() {
+                let Ok((block, block_layers)) = block_q.get(block_entity) else {
+                    continue;
+                };
+                if !layers.interacts_with(&CollisionLayers::of(&block.collider_type, block_layers))
+                {
+                    continue;
+                }
+                if let Some((t, normal)) = swept_circle_polygon(from, to, radius, &block.points) {
+                    let depth = circle_polygon_push(from.lerp(to, t), radius, &block.points)
+                        .map_or(0., |push| push.length());
+                    hit(t, block_entity, normal, depth);
+                }
+            }
 
-            if distance < collision_distance {
-                if player.current_health > 0 {
-                    player.current_health -= 200
-                } else {
-                    println!("Player: {} is dead", player.id)
+            bodies.query(
+                from.min(to) - Vec2::splat(radius),
+                from.max(to) + Vec2::splat(radius),
+                &mut candidates,
+            );
+            for &other in candidates.iter() {
+                let Ok((transform, other_collider, other_layers)) = circle_q.get(other) else {
+                    continue;
+                };
+                if !layers.interacts_with(&CollisionLayers::of(
+                    &other_collider.collider_type,
+                    other_layers,
+                )) {
+                    continue;
+                }
+                let center = transform.translation.truncate();
+                if let Some(t) = ray_circle(from, to - from, center, radius + other_collider.radius)
+                {
+                    let offset = from.lerp(to, t) - center;
+                    let depth = radius + other_collider.radius - offset.length();
+                    hit(t, other, offset.normalize_or_zero(), depth);
                 }
-                let bullet_sound = "sounds/impact.ogg";
-                let value = BulletImpactSoundtrackPlayer::new(bullet_sound, &mut asset_server);
-                entities_to_despawn.push(entity);
-                BulletImpactSoundtrackPlayer::play_impact_sound(&value, &mut commands, 0.3);
             }
-        }
 
-        // Despawn entities after iteration
-        for entity in entities_to_despawn {
-            commands.entity(entity).despawn();
+            if let Some((_, collision)) = first {
+                collisions.send(collision);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::colliders_o::*;
+    use bevy::prelude::*;
+
+    const EPSILON: f32 = 1e-3;
+
+    // Counter-clockwise, like blocks store them.
+    fn square(min: Vec2, max: Vec2) -> Vec<Vec2> {
+        vec![
+            Vec2::new(min.x, min.y),
+            Vec2::new(max.x, min.y),
+            Vec2::new(max.x, max.y),
+            Vec2::new(min.x, max.y),
+        ]
+    }
+
+    fn unit_square() -> Vec<Vec2> {
+        square(Vec2::splat(-50.), Vec2::splat(50.))
+    }
+
+    #[test]
+    fn circle_overlapping_an_edge_is_pushed_straight_out() {
+        let push = circle_polygon_push(Vec2::new(60., 0.), 20., &unit_square()).unwrap();
+        assert!(push.distance(Vec2::new(10., 0.)) < EPSILON, "{}", push);
+    }
+
+    #[test]
+    fn circle_clear_of_the_polygon_is_not_pushed() {
+        assert_eq!(
+            circle_polygon_push(Vec2::new(80., 0.), 20., &unit_square()),
+            None
+        );
+        // Inside the bounding box of the grown square, but past the rounded corner.
+        assert_eq!(
+            circle_polygon_push(Vec2::new(65., 65.), 20., &unit_square()),
+            None
+        );
+    }
+
+    #[test]
+    fn circle_near_a_corner_is_pushed_away_from_it() {
+        let push = circle_polygon_push(Vec2::new(60., 60.), 20., &unit_square()).unwrap();
+        let expected = Vec2::ONE.normalize() * (20. - Vec2::splat(10.).length());
+        assert!(push.distance(expected) < EPSILON, "{}", push);
+    }
+
+    #[test]
+    fn rotated_polygons_push_along_their_own_edges() {
+        let diamond = vec![
+            Vec2::new(0., -50.),
+            Vec2::new(50., 0.),
+            Vec2::new(0., 50.),
+            Vec2::new(-50., 0.),
+        ];
+        // Its edge facing up-right passes 25 * sqrt(2) from the origin.
+        let center = Vec2::splat(20.);
+        let push = circle_polygon_push(center, 10., &diamond).unwrap();
+        let normal = Vec2::ONE.normalize();
+        let depth = 10. - (center.dot(normal) - 25. * std::f32::consts::SQRT_2);
+        assert!(push.distance(normal * depth) < EPSILON, "{}", push);
+    }
+
+    #[test]
+    fn segments_hit_polygons_they_cross_or_sit_in() {
+        let square = unit_square();
+        assert!(segment_hits_polygon(
+            Vec2::new(-100., 0.),
+            Vec2::new(100., 0.),
+            &square
+        ));
+        assert!(segment_hits_polygon(
+            Vec2::new(-10., 0.),
+            Vec2::new(10., 0.),
+            &square
+        ));
+        assert!(!segment_hits_polygon(
+            Vec2::new(-100., 60.),
+            Vec2::new(100., 60.),
+            &square
+        ));
+        // Its bounding box overlaps the square, but it passes the corner.
+        assert!(!segment_hits_polygon(
+            Vec2::new(40., 100.),
+            Vec2::new(100., 40.),
+            &square
+        ));
+    }
+
+    #[test]
+    fn fast_circles_are_caught_before_passing_through_thin_walls() {
+        let wall = square(Vec2::new(-5., -100.), Vec2::new(5., 100.));
+        let (t, normal) =
+            swept_circle_polygon(Vec2::new(-200., 0.), Vec2::new(200., 0.), 10., &wall).unwrap();
+        // First touches when its center is a radius short of the wall's face.
+        assert!((t - 185. / 400.).abs() < EPSILON, "{}", t);
+        assert!(normal.distance(Vec2::NEG_X) < EPSILON, "{}", normal);
+    }
+
+    #[test]
+    fn swept_circles_that_miss_or_stop_short_are_not_hits() {
+        let wall = square(Vec2::new(-5., -100.), Vec2::new(5., 100.));
+        let r = 10.;
+        assert_eq!(
+            swept_circle_polygon(Vec2::new(-200., 150.), Vec2::new(200., 150.), r, &wall),
+            None
+        );
+        assert_eq!(
+            swept_circle_polygon(Vec2::new(-200., 0.), Vec2::new(-50., 0.), r, &wall),
+            None
+        );
+    }
+
+    #[test]
+    fn swept_circles_clip_corners() {
+        let wall = unit_square();
+        // Passes the corner at (50, 50) 5 closer than its radius.
+        let hit = swept_circle_polygon(Vec2::new(-200., 65.), Vec2::new(200., 65.), 20., &wall);
+        let (_, normal) = hit.unwrap();
+        assert!(normal.y > 0. && normal.x < 0., "{}", normal);
+    }
+
+    #[test]
+    fn swept_circles_starting_inside_hit_straight_away() {
+        let (t, _) =
+            swept_circle_polygon(Vec2::new(45., 0.), Vec2::new(200., 0.), 10., &unit_square())
+                .unwrap();
+        assert_eq!(t, 0.);
+    }
+
+    #[test]
+    fn rays_only_hit_circles_they_move_into() {
+        let t = ray_circle(Vec2::new(-100., 0.), Vec2::new(200., 0.), Vec2::ZERO, 20.).unwrap();
+        assert!((t - 0.4).abs() < EPSILON, "{}", t);
+        // Leaving from inside, like a shot from the shooter's own body.
+        assert_eq!(
+            ray_circle(Vec2::ZERO, Vec2::new(200., 0.), Vec2::ZERO, 20.),
+            None
+        );
+    }
+}
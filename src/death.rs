@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use crate::{
+    enemy::EnemyPlayer,
+    health::Health,
+    map::{CurrentMap, MapData},
+    player::Player,
+};
+
+pub const RESPAWN_SECONDS: f32 = 4.;
+pub const SPAWN_PROTECTION_SECONDS: f32 = 2.;
+const DEATH_ANIMATION_SECONDS: f32 = 0.6;
+
+// Dead: for the local player input is ignored, the camera follows `killer` and a respawn
+// is asked for once the timer runs out. Remote players only get the animation.
+#[derive(Component)]
+pub struct Dead {
+    pub killer: Option<Entity>,
+    respawn: Timer,
+    requested: bool,
+}
+
+impl Dead {
+    pub fn new(killer: Option<Entity>) -> Self {
+        Dead {
+            killer,
+            respawn: Timer::from_seconds(RESPAWN_SECONDS, TimerMode::Once),
+            requested: false,
+        }
+    }
+}
+
+// Freshly respawned; bullets can't hurt the player until it runs out.
+#[derive(Component)]
+pub struct SpawnProtection(pub Timer);
+
+impl Default for SpawnProtection {
+    fn default() -> Self {
+        SpawnProtection(Timer::from_seconds(
+            SPAWN_PROTECTION_SECONDS,
+            TimerMode::Once,
+        ))
+    }
+}
+
+// The respawn timer ran out. Online the server picks where to come back, offline
+// `respawn_locally` does.
+#[derive(Event)]
+pub struct RespawnRequested;
+
+#[derive(Event)]
+pub struct Respawned {
+    pub position: (f32, f32),
+}
+
+pub struct DeathPlugin;
+
+impl Plugin for DeathPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RespawnRequested>()
+            .add_event::<Respawned>()
+            .add_systems(
+                Update,
+                (animate_death, apply_respawn, tick_spawn_protection).chain(),
+            );
+    }
+}
+
+// For gating anything the player does on purpose: moving, aiming and shooting. There's
+// no player to act until the loadout is picked, so that counts as not alive too.
+pub fn player_alive(player_q: Query<Has<Dead>, With<Player>>) -> bool {
+    matches!(player_q.get_single(), Ok(false))
+}
+
+pub fn animate_death(
+    mut dead_q: Query<(&mut Dead, &mut Transform, Has<Player>)>,
+    mut requests: EventWriter<RespawnRequested>,
+    time: Res<Time>,
+) {
+    for (mut dead, mut transform, local) in dead_q.iter_mut() {
+        dead.respawn.tick(time.delta());
+
+        // Spin down to a small husk, then wait there.
+        let t = (dead.respawn.elapsed_secs() / DEATH_ANIMATION_SECONDS).min(1.);
+        transform.scale = Vec3::splat(1. - 0.7 * t);
+        transform.rotation = Quat::from_rotation_z(t * std::f32::consts::TAU);
+
+        if local && dead.respawn.finished() && !dead.requested {
+            dead.requested = true;
+            requests.send(RespawnRequested);
+        }
+    }
+}
+
+// Offline there's no server to pick a spawn point, so it's done here, away from the AI.
+pub fn respawn_locally(
+    mut requests: EventReader<RespawnRequested>,
+    mut respawns: EventWriter<Respawned>,
+    enemy_q: Query<&Transform, With<EnemyPlayer>>,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<MapData>>,
+) {
+    for _ in requests.read() {
+        let Some(map) = maps.get(&current_map.handle) else {
+            continue;
+        };
+        let enemies: Vec<Vec2> = enemy_q
+            .iter()
+            .map(|transform| transform.translation.truncate())
+            .collect();
+        respawns.send(Respawned {
+            position: map.pick_spawn(&enemies),
+        });
+    }
+}
+
+// The local player while it's waiting to come back.
+type DeadPlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static mut Transform, &'static mut Health),
+    (With<Player>, With<Dead>),
+>;
+
+pub fn apply_respawn(
+    mut commands: Commands,
+    mut respawns: EventReader<Respawned>,
+    mut player_q: DeadPlayerQuery,
+) {
+    for respawn in respawns.read() {
+        let Ok((entity, mut transform, mut health)) = player_q.get_single_mut() else {
+            continue;
+        };
+        transform.translation.x = respawn.position.0;
+        transform.translation.y = respawn.position.1;
+        transform.scale = Vec3::ONE;
+        transform.rotation = Quat::IDENTITY;
+        health.current = health.max;
+
+        commands
+            .entity(entity)
+            .remove::<Dead>()
+            .insert(SpawnProtection::default());
+    }
+}
+
+pub fn tick_spawn_protection(
+    mut commands: Commands,
+    mut protected_q: Query<(Entity, &mut SpawnProtection, &mut Visibility)>,
+    time: Res<Time>,
+) {
+    for (entity, mut protection, mut visibility) in protected_q.iter_mut() {
+        protection.0.tick(time.delta());
+        if protection.0.finished() {
+            commands.entity(entity).remove::<SpawnProtection>();
+            *visibility = Visibility::Inherited;
+            continue;
+        }
+
+        // Blink so everyone can see it can't be hurt yet.
+        let on = ((protection.0.elapsed_secs() * 10.) as u32).is_multiple_of(2);
+        *visibility = if on {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
@@ -0,0 +1,241 @@
+use bevy::{prelude::*, utils::HashMap};
+use rand::Rng;
+
+use crate::{audio::audioO::BlockBreakSoundtrackPlayer, colliders::colliders_o::BlockCollider};
+
+const CHIPS_PER_HIT: usize = 3;
+const DEBRIS_PER_BREAK: usize = 18;
+
+// A map wall that can be shot down. `index` is its position in the map's walls, which
+// is how the server and clients agree on which block is which.
+#[derive(Component)]
+pub struct Destructible {
+    pub index: usize,
+    pub health: i32,
+    pub max_health: i32,
+}
+
+impl Destructible {
+    pub fn new(index: usize, health: i32) -> Self {
+        Destructible {
+            index,
+            health,
+            max_health: health,
+        }
+    }
+}
+
+// A bullet hit a destructible block. Online, only hits from the local player's own
+// shots are reported to the server, so each hit is counted once.
+#[derive(Event)]
+pub struct BlockHit {
+    pub index: usize,
+    pub damage: i32,
+    pub position: Vec2,
+    pub local: bool,
+}
+
+// The block's health has been settled, by the server or locally when offline.
+#[derive(Event)]
+pub struct BlockHealthChanged {
+    pub index: usize,
+    pub health: i32,
+}
+
+// Latest settled health of every block that's been hit, by index. The map can be
+// respawned under the blocks by a reload, or only get loaded after the server has told us
+// about them, and this is what puts the damage back.
+#[derive(Resource, Default)]
+pub struct BlockHealthLog(pub HashMap<usize, i32>);
+
+#[derive(Component)]
+pub struct Debris {
+    velocity: Vec2,
+    spin: f32,
+    size: f32,
+    lifetime: Timer,
+}
+
+pub struct DestructionPlugin;
+
+impl Plugin for DestructionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BlockHit>()
+            .add_event::<BlockHealthChanged>()
+            .init_resource::<BlockHealthLog>()
+            .add_systems(
+                Update,
+                (
+                    chip_blocks,
+                    apply_block_health,
+                    show_block_damage,
+                    move_debris,
+                )
+                    .chain(),
+            );
+    }
+}
+
+// Offline there's no server to arbitrate, so hits are applied straight away.
+pub fn resolve_block_hits_locally(
+    mut hits: EventReader<BlockHit>,
+    blocks: Query<&Destructible>,
+    mut changes: EventWriter<BlockHealthChanged>,
+) {
+    // Several hits on one block can land in the same frame.
+    let mut health: HashMap<usize, i32> = HashMap::new();
+    for hit in hits.read() {
+        let Some(block) = blocks.iter().find(|block| block.index == hit.index) else {
+            continue;
+        };
+        let health = health.entry(hit.index).or_insert(block.health);
+        *health -= hit.damage;
+        changes.send(BlockHealthChanged {
+            index: hit.index,
+            health: *health,
+        });
+    }
+}
+
+fn damage_color(health: i32, max_health: i32) -> Color {
+    let fraction = health as f32 / max_health.max(1) as f32;
+    if fraction > 0.66 {
+        Color::srgb(0.55, 0.47, 0.36)
+    } else if fraction > 0.33 {
+        Color::srgb(0.45, 0.36, 0.27)
+    } else {
+        Color::srgb(0.34, 0.25, 0.19)
+    }
+}
+
+fn spawn_debris(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    area: Rect,
+    count: usize,
+    speed: f32,
+    color: Color,
+) {
+    let mut rng = rand::thread_rng();
+    let mesh = meshes.add(Rectangle::new(14., 14.));
+    let material = materials.add(ColorMaterial::from(color));
+
+    for _ in 0..count {
+        let position = Vec2::new(
+            rng.gen_range(area.min.x..=area.max.x),
+            rng.gen_range(area.min.y..=area.max.y),
+        );
+        let direction = Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU));
+        let size = rng.gen_range(0.5..1.2);
+        commands.spawn((
+            Debris {
+                velocity: direction * speed * rng.gen_range(0.4..1.0),
+                spin: rng.gen_range(-10.0..10.0),
+                size,
+                lifetime: Timer::from_seconds(rng.gen_range(0.5..1.0), TimerMode::Once),
+            },
+            Mesh2d(mesh.clone()),
+            MeshMaterial2d(material.clone()),
+            Transform::from_xyz(position.x, position.y, 2.0).with_scale(Vec3::splat(size)),
+        ));
+    }
+}
+
+pub fn chip_blocks(
+    mut hits: EventReader<BlockHit>,
+    blocks: Query<&Destructible>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for hit in hits.read() {
+        let Some(block) = blocks.iter().find(|block| block.index == hit.index) else {
+            continue;
+        };
+        spawn_debris(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            Rect::from_center_size(hit.position, Vec2::splat(10.)),
+            CHIPS_PER_HIT,
+            400.,
+            damage_color(block.health, block.max_health),
+        );
+    }
+}
+
+pub fn apply_block_health(
+    mut changes: EventReader<BlockHealthChanged>,
+    mut log: ResMut<BlockHealthLog>,
+    mut blocks: Query<(Entity, &mut Destructible, &BlockCollider)>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for change in changes.read() {
+        log.0.insert(change.index, change.health);
+        let Some((entity, mut block, collider)) = blocks
+            .iter_mut()
+            .find(|(_, block, _)| block.index == change.index)
+        else {
+            continue;
+        };
+        if block.health <= 0 {
+            continue;
+        }
+        block.health = change.health;
+
+        if block.health <= 0 {
+            // Removing the collider is enough for the navigation grid to open up.
+            commands.entity(entity).despawn_recursive();
+            spawn_debris(
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                Rect::from_corners(collider.min, collider.max),
+                DEBRIS_PER_BREAK,
+                700.,
+                damage_color(0, block.max_health),
+            );
+            let sound = BlockBreakSoundtrackPlayer::new("sounds/impact.ogg", &asset_server);
+            BlockBreakSoundtrackPlayer::play_break_sound(&sound, &mut commands);
+        }
+    }
+}
+
+pub fn show_block_damage(
+    blocks: Query<(&Destructible, &MeshMaterial2d<ColorMaterial>), Changed<Destructible>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for (block, material) in blocks.iter() {
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color = damage_color(block.health, block.max_health);
+        }
+    }
+}
+
+pub fn move_debris(
+    mut commands: Commands,
+    mut debris_q: Query<(Entity, &mut Debris, &mut Transform)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (entity, mut debris, mut transform) in debris_q.iter_mut() {
+        debris.lifetime.tick(time.delta());
+        if debris.lifetime.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let velocity = debris.velocity;
+        transform.translation += (velocity * dt).extend(0.);
+        transform.rotate_z(debris.spin * dt);
+        debris.velocity *= 1. - (4. * dt).min(1.);
+
+        // Shrink away over the last part of its life.
+        let remaining = debris.lifetime.fraction_remaining();
+        transform.scale = Vec3::splat(debris.size * (remaining / 0.4).min(1.));
+    }
+}
@@ -0,0 +1,680 @@
+use std::path::PathBuf;
+
+use bevy::{
+    asset::io::file::FileAssetReader, ecs::system::SystemParam, input::mouse::MouseWheel,
+    prelude::*,
+};
+
+use crate::{
+    block::Block,
+    colliders::colliders_o::polygon_contains,
+    map::{CurrentMap, MapBounds, MapData, MapState, PickupData, PickupKind, WallData},
+};
+
+const GRID_SIZE: f32 = 100.;
+const SPAWN_PICK_RADIUS: f32 = 80.;
+const PICKUP_PICK_RADIUS: f32 = 60.;
+const HANDLE_PICK_RADIUS: f32 = 40.;
+const MIN_BLOCK_SIZE: f32 = 20.;
+const ROTATE_STEP: f32 = 15.;
+// H cycles a selected wall through these, then back to indestructible.
+const WALL_HEALTH_STEPS: [i32; 3] = [500, 1000, 2000];
+
+#[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GameMode {
+    #[default]
+    Play,
+    Editor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorTool {
+    Block,
+    SpawnPoint,
+    Pickup,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Selection {
+    Wall(usize),
+    Spawn(usize),
+    Pickup(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Drag {
+    Create { start: Vec2 },
+    Move { selection: Selection, offset: Vec2 },
+    // `anchor` is the corner that stays put, in world space.
+    Resize { wall: usize, anchor: Vec2 },
+}
+
+#[derive(Resource)]
+pub struct Editor {
+    tool: EditorTool,
+    pickup_kind: PickupKind,
+    snap: bool,
+    selection: Option<Selection>,
+    drag: Option<Drag>,
+    // The map as it was when the drag started, recorded for undo once the drag
+    // actually changes something.
+    before_drag: Option<MapData>,
+    undo: Vec<MapData>,
+    redo: Vec<MapData>,
+    status: String,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Editor {
+            tool: EditorTool::Block,
+            pickup_kind: PickupKind::Health,
+            snap: true,
+            selection: None,
+            drag: None,
+            before_drag: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            status: String::new(),
+        }
+    }
+}
+
+impl Editor {
+    fn snap(&self, position: Vec2) -> Vec2 {
+        if self.snap {
+            (position / GRID_SIZE).round() * GRID_SIZE
+        } else {
+            position
+        }
+    }
+
+    fn record(&mut self, map: &MapData) {
+        self.undo.push(map.clone());
+        self.redo.clear();
+    }
+
+    fn start_drag(&mut self, drag: Drag, map: &MapData) {
+        self.drag = Some(drag);
+        self.before_drag = Some(map.clone());
+    }
+
+    // Called whenever a drag changes the map; only the first change is recorded.
+    fn record_drag(&mut self) {
+        if let Some(map) = self.before_drag.take() {
+            self.record(&map);
+        }
+    }
+}
+
+#[derive(Component)]
+struct EditorHud;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<GameMode>()
+            .init_resource::<Editor>()
+            .add_systems(Update, toggle_editor.run_if(in_state(MapState::Ready)))
+            .add_systems(OnEnter(GameMode::Editor), spawn_editor_hud)
+            .add_systems(OnExit(GameMode::Editor), leave_editor)
+            .add_systems(
+                Update,
+                (editor_camera, editor_input, draw_editor, update_editor_hud)
+                    .chain()
+                    .run_if(in_state(GameMode::Editor)),
+            );
+    }
+}
+
+// Bounds of the wall's points before rotation, which is what moving and resizing act on.
+fn wall_rect(wall: &WallData) -> Rect {
+    wall.points.iter().fold(Rect::EMPTY, |rect, &(x, y)| {
+        rect.union_point(Vec2::new(x, y))
+    })
+}
+
+// Moves and scales the wall's points to fill `rect`, keeping its shape.
+fn set_wall_rect(wall: &mut WallData, rect: Rect) {
+    let old = wall_rect(wall);
+    let scale = rect.size() / old.size().max(Vec2::splat(f32::EPSILON));
+    for point in wall.points.iter_mut() {
+        let moved = rect.min + (Vec2::new(point.0, point.1) - old.min) * scale;
+        *point = (moved.x, moved.y);
+    }
+}
+
+// What the wall's rotation turns around: the center of its points before rotation.
+fn wall_pivot(wall: &WallData) -> Vec2 {
+    Block::center_of(&Block::from_points(&wall.points, 0.).points)
+}
+
+// From the world into the wall's own space, where `wall_rect` is axis aligned.
+fn to_wall_space(wall: &WallData, position: Vec2) -> Vec2 {
+    let pivot = wall_pivot(wall);
+    pivot + Vec2::from_angle(-wall.rotation.to_radians()).rotate(position - pivot)
+}
+
+fn from_wall_space(wall: &WallData, position: Vec2) -> Vec2 {
+    let pivot = wall_pivot(wall);
+    pivot + Vec2::from_angle(wall.rotation.to_radians()).rotate(position - pivot)
+}
+
+fn wall_corners(wall: &WallData) -> [Vec2; 4] {
+    let rect = wall_rect(wall);
+    [
+        rect.min,
+        rect.max,
+        Vec2::new(rect.min.x, rect.max.y),
+        Vec2::new(rect.max.x, rect.min.y),
+    ]
+}
+
+// Resizes a rotated wall to `rect` in its own space. Resizing moves the pivot, which
+// would swing the whole wall around, so it's shifted back to keep `rect` where it was.
+fn resize_wall(wall: &mut WallData, rect: Rect) {
+    let before = from_wall_space(wall, rect.min);
+    set_wall_rect(wall, rect);
+    let shift = before - from_wall_space(wall, rect.min);
+    for point in wall.points.iter_mut() {
+        *point = (point.0 + shift.x, point.1 + shift.y);
+    }
+}
+
+// Where the mouse is pointing on the map, and how far the view is zoomed out.
+#[derive(SystemParam)]
+struct EditorCursor<'w, 's> {
+    windows: Query<'w, 's, &'static Window>,
+    camera_q: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    projection_q: Query<'w, 's, &'static OrthographicProjection>,
+}
+
+impl EditorCursor<'_, '_> {
+    fn world(&self) -> Option<Vec2> {
+        let cursor = self.windows.get_single().ok()?.cursor_position()?;
+        let (camera, camera_transform) = self.camera_q.get_single().ok()?;
+        camera.viewport_to_world_2d(camera_transform, cursor).ok()
+    }
+
+    fn scale(&self) -> f32 {
+        self.projection_q.get_single().map_or(1., |p| p.scale)
+    }
+}
+
+fn pick(map: &MapData, position: Vec2) -> Option<Selection> {
+    let near =
+        |point: (f32, f32), radius: f32| Vec2::new(point.0, point.1).distance(position) < radius;
+
+    if let Some(i) = map
+        .pickups
+        .iter()
+        .position(|pickup| near(pickup.position, PICKUP_PICK_RADIUS))
+    {
+        return Some(Selection::Pickup(i));
+    }
+    if let Some(i) = map
+        .spawn_points
+        .iter()
+        .position(|&spawn| near(spawn, SPAWN_PICK_RADIUS))
+    {
+        return Some(Selection::Spawn(i));
+    }
+    // Last wall first, so the one drawn on top wins.
+    map.walls
+        .iter()
+        .rposition(|wall| polygon_contains(&wall.block().points, position))
+        .map(Selection::Wall)
+}
+
+fn selection_anchor(map: &MapData, selection: Selection) -> Vec2 {
+    match selection {
+        Selection::Wall(i) => wall_rect(&map.walls[i]).min,
+        Selection::Spawn(i) => Vec2::new(map.spawn_points[i].0, map.spawn_points[i].1),
+        Selection::Pickup(i) => Vec2::new(map.pickups[i].position.0, map.pickups[i].position.1),
+    }
+}
+
+fn save_map(path: &str, map: &MapData) -> Result<PathBuf, String> {
+    let file = FileAssetReader::get_base_path().join("assets").join(path);
+    let json = serde_json::to_string_pretty(map).map_err(|e| e.to_string())?;
+    std::fs::write(&file, json).map_err(|e| e.to_string())?;
+    Ok(file)
+}
+
+fn toggle_editor(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mode: Res<State<GameMode>>,
+    mut next_mode: ResMut<NextState<GameMode>>,
+) {
+    if keyboard.just_pressed(KeyCode::F1) {
+        next_mode.set(match mode.get() {
+            GameMode::Play => GameMode::Editor,
+            GameMode::Editor => GameMode::Play,
+        });
+    }
+}
+
+fn spawn_editor_hud(mut commands: Commands) {
+    commands.spawn((
+        EditorHud,
+        Text::new(""),
+        TextFont {
+            font_size: 22.,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            left: Val::Px(12.),
+            ..default()
+        },
+    ));
+}
+
+fn leave_editor(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    hud_q: Query<Entity, With<EditorHud>>,
+    mut projection_q: Query<&mut OrthographicProjection>,
+) {
+    for entity in hud_q.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for mut projection in projection_q.iter_mut() {
+        projection.scale = 1.;
+    }
+    editor.drag = None;
+    editor.selection = None;
+}
+
+fn editor_camera(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut scroll: EventReader<MouseWheel>,
+    mut camera_q: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+    bounds: Res<MapBounds>,
+) {
+    let Ok((mut transform, mut projection)) = camera_q.get_single_mut() else {
+        return;
+    };
+
+    for event in scroll.read() {
+        projection.scale = (projection.scale * (1. - event.y * 0.1)).clamp(0.5, 8.);
+    }
+
+    let mut direction = Vec2::ZERO;
+    if keyboard.pressed(KeyCode::KeyW) {
+        direction.y += 1.;
+    }
+    if keyboard.pressed(KeyCode::KeyS) {
+        direction.y -= 1.;
+    }
+    if keyboard.pressed(KeyCode::KeyA) {
+        direction.x -= 1.;
+    }
+    if keyboard.pressed(KeyCode::KeyD) {
+        direction.x += 1.;
+    }
+
+    let step = direction.normalize_or_zero() * 1500. * projection.scale * time.delta_secs();
+    let position = (transform.translation.truncate() + step).clamp(bounds.min(), bounds.max());
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
+}
+
+fn editor_input(
+    mut editor: ResMut<Editor>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    cursor: EditorCursor,
+    current_map: Res<CurrentMap>,
+    mut maps: ResMut<Assets<MapData>>,
+) {
+    let Some(map) = maps.get(&current_map.handle).cloned() else {
+        return;
+    };
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    if keyboard.just_pressed(KeyCode::Digit1) {
+        editor.tool = EditorTool::Block;
+    }
+    if keyboard.just_pressed(KeyCode::Digit2) {
+        editor.tool = EditorTool::SpawnPoint;
+    }
+    if keyboard.just_pressed(KeyCode::Digit3) {
+        editor.tool = EditorTool::Pickup;
+    }
+    if keyboard.just_pressed(KeyCode::KeyQ) {
+        editor.pickup_kind = match editor.pickup_kind {
+            PickupKind::Health => PickupKind::Ammo,
+            PickupKind::Ammo => PickupKind::SpeedBoost,
+            PickupKind::SpeedBoost => PickupKind::DamageBoost,
+            PickupKind::DamageBoost => PickupKind::Shield,
+            PickupKind::Shield => PickupKind::Health,
+        };
+    }
+    if keyboard.just_pressed(KeyCode::KeyG) {
+        editor.snap = !editor.snap;
+    }
+    if keyboard.just_pressed(KeyCode::KeyR) {
+        if let Some(Selection::Wall(i)) = editor.selection {
+            editor.record(&map);
+            let step = if shift { -ROTATE_STEP } else { ROTATE_STEP };
+            if let Some(map) = maps.get_mut(&current_map.handle) {
+                map.walls[i].rotation = (map.walls[i].rotation + step).rem_euclid(360.);
+            }
+        }
+        return;
+    }
+    if keyboard.just_pressed(KeyCode::KeyH) {
+        if let Some(Selection::Wall(i)) = editor.selection {
+            editor.record(&map);
+            if let Some(map) = maps.get_mut(&current_map.handle) {
+                let wall = &mut map.walls[i];
+                wall.health = match wall.health {
+                    None => Some(WALL_HEALTH_STEPS[0]),
+                    Some(health) => WALL_HEALTH_STEPS
+                        .iter()
+                        .position(|&step| step == health)
+                        .and_then(|step| WALL_HEALTH_STEPS.get(step + 1))
+                        .copied(),
+                };
+                editor.status = match wall.health {
+                    Some(health) => format!("wall health: {}", health),
+                    None => String::from("wall health: indestructible"),
+                };
+            }
+        }
+        return;
+    }
+
+    let undo = ctrl && keyboard.just_pressed(KeyCode::KeyZ) && !shift;
+    let redo = ctrl
+        && (keyboard.just_pressed(KeyCode::KeyY)
+            || (keyboard.just_pressed(KeyCode::KeyZ) && shift));
+    if undo || redo {
+        let restored = if undo {
+            editor.undo.pop()
+        } else {
+            editor.redo.pop()
+        };
+        if let Some(restored) = restored {
+            if undo {
+                editor.redo.push(map);
+            } else {
+                editor.undo.push(map);
+            }
+            editor.selection = None;
+            editor.drag = None;
+            editor.before_drag = None;
+            if let Some(current) = maps.get_mut(&current_map.handle) {
+                *current = restored;
+            }
+        }
+        return;
+    }
+
+    if ctrl && keyboard.just_pressed(KeyCode::KeyS) {
+        editor.status = match save_map(&current_map.path, &map) {
+            Ok(file) => format!("Saved to {}", file.display()),
+            Err(e) => format!("Save failed: {}", e),
+        };
+        return;
+    }
+
+    if keyboard.any_just_pressed([KeyCode::Delete, KeyCode::Backspace]) {
+        if let Some(selection) = editor.selection.take() {
+            editor.record(&map);
+            if let Some(map) = maps.get_mut(&current_map.handle) {
+                remove(map, selection);
+            }
+        }
+        return;
+    }
+
+    let scale = cursor.scale();
+    let Some(cursor) = cursor.world() else {
+        return;
+    };
+
+    if mouse.just_pressed(MouseButton::Right) {
+        if let Some(selection) = pick(&map, cursor) {
+            editor.record(&map);
+            editor.selection = None;
+            if let Some(map) = maps.get_mut(&current_map.handle) {
+                remove(map, selection);
+            }
+        }
+        return;
+    }
+
+    if mouse.just_pressed(MouseButton::Left) {
+        // Corner handles of the selected wall take priority so it can be resized.
+        if let Some(Selection::Wall(i)) = editor.selection {
+            let wall = &map.walls[i];
+            let rect = wall_rect(wall);
+            let local_cursor = to_wall_space(wall, cursor);
+            if let Some(corner) = wall_corners(wall)
+                .iter()
+                .find(|corner| corner.distance(local_cursor) < HANDLE_PICK_RADIUS * scale)
+            {
+                let anchor = from_wall_space(wall, rect.min + rect.max - *corner);
+                editor.start_drag(Drag::Resize { wall: i, anchor }, &map);
+                return;
+            }
+        }
+
+        if let Some(selection) = pick(&map, cursor) {
+            editor.selection = Some(selection);
+            let offset = selection_anchor(&map, selection) - cursor;
+            editor.start_drag(Drag::Move { selection, offset }, &map);
+            return;
+        }
+
+        editor.selection = None;
+        let position = editor.snap(cursor);
+        match editor.tool {
+            EditorTool::Block => editor.drag = Some(Drag::Create { start: position }),
+            EditorTool::SpawnPoint => {
+                editor.record(&map);
+                if let Some(map) = maps.get_mut(&current_map.handle) {
+                    map.spawn_points.push((position.x, position.y));
+                    editor.selection = Some(Selection::Spawn(map.spawn_points.len() - 1));
+                }
+            }
+            EditorTool::Pickup => {
+                editor.record(&map);
+                let kind = editor.pickup_kind;
+                if let Some(map) = maps.get_mut(&current_map.handle) {
+                    map.pickups.push(PickupData {
+                        kind,
+                        position: (position.x, position.y),
+                        respawn_secs: 20.,
+                    });
+                    editor.selection = Some(Selection::Pickup(map.pickups.len() - 1));
+                }
+            }
+        }
+        return;
+    }
+
+    let Some(drag) = editor.drag else {
+        return;
+    };
+    let position = editor.snap(cursor);
+
+    if mouse.just_released(MouseButton::Left) {
+        editor.drag = None;
+        editor.before_drag = None;
+        if let Drag::Create { start } = drag {
+            let rect = Rect::from_corners(start, position);
+            if rect.width() >= MIN_BLOCK_SIZE && rect.height() >= MIN_BLOCK_SIZE {
+                editor.record(&map);
+                if let Some(map) = maps.get_mut(&current_map.handle) {
+                    map.walls.push(WallData::from_rect(rect.min, rect.max));
+                    editor.selection = Some(Selection::Wall(map.walls.len() - 1));
+                }
+            }
+        }
+        return;
+    }
+
+    // Only touch the asset when something actually moved, since every change respawns
+    // the map's walls.
+    match drag {
+        Drag::Create { .. } => {}
+        Drag::Move { selection, offset } => {
+            let target = editor.snap(cursor + offset);
+            if target != selection_anchor(&map, selection) {
+                editor.record_drag();
+                if let Some(map) = maps.get_mut(&current_map.handle) {
+                    match selection {
+                        Selection::Wall(i) => {
+                            let rect = wall_rect(&map.walls[i]);
+                            let moved = Rect::from_corners(target, target + rect.size());
+                            set_wall_rect(&mut map.walls[i], moved);
+                        }
+                        Selection::Spawn(i) => map.spawn_points[i] = (target.x, target.y),
+                        Selection::Pickup(i) => map.pickups[i].position = (target.x, target.y),
+                    }
+                }
+            }
+        }
+        Drag::Resize { wall, anchor } => {
+            let current = &map.walls[wall];
+            let rect = Rect::from_corners(
+                to_wall_space(current, anchor),
+                to_wall_space(current, position),
+            );
+            if rect.width() >= MIN_BLOCK_SIZE
+                && rect.height() >= MIN_BLOCK_SIZE
+                && rect != wall_rect(current)
+            {
+                editor.record_drag();
+                if let Some(map) = maps.get_mut(&current_map.handle) {
+                    resize_wall(&mut map.walls[wall], rect);
+                }
+            }
+        }
+    }
+}
+
+fn remove(map: &mut MapData, selection: Selection) {
+    match selection {
+        Selection::Wall(i) => {
+            map.walls.remove(i);
+        }
+        Selection::Spawn(i) => {
+            map.spawn_points.remove(i);
+        }
+        Selection::Pickup(i) => {
+            map.pickups.remove(i);
+        }
+    }
+}
+
+fn draw_editor(
+    mut gizmos: Gizmos,
+    editor: Res<Editor>,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<MapData>>,
+    cursor: EditorCursor,
+) {
+    let Some(map) = maps.get(&current_map.handle) else {
+        return;
+    };
+
+    if editor.snap {
+        let cells = (Vec2::new(map.bounds.width, map.bounds.height) / GRID_SIZE).as_uvec2();
+        gizmos.grid_2d(
+            Vec2::ZERO,
+            cells,
+            Vec2::splat(GRID_SIZE),
+            Color::srgba(1., 1., 1., 0.05),
+        );
+    }
+    gizmos.rect_2d(
+        Vec2::ZERO,
+        Vec2::new(map.bounds.width, map.bounds.height),
+        Color::srgb(0.9, 0.2, 0.2),
+    );
+
+    for (i, wall) in map.walls.iter().enumerate() {
+        let selected = editor.selection == Some(Selection::Wall(i));
+        let color = if selected {
+            Color::srgb(1., 0.9, 0.2)
+        } else {
+            Color::srgb(0.8, 0.8, 0.8)
+        };
+        let mut outline = wall.block().points;
+        outline.extend(outline.first().copied());
+        gizmos.linestrip_2d(outline, color);
+        if selected {
+            let rotation = Rot2::degrees(wall.rotation);
+            for corner in wall_corners(wall) {
+                gizmos.rect_2d(
+                    Isometry2d::new(from_wall_space(wall, corner), rotation),
+                    Vec2::splat(HANDLE_PICK_RADIUS),
+                    color,
+                );
+            }
+        }
+    }
+
+    for (i, &spawn) in map.spawn_points.iter().enumerate() {
+        let position = Vec2::new(spawn.0, spawn.1);
+        let color = if editor.selection == Some(Selection::Spawn(i)) {
+            Color::srgb(1., 0.9, 0.2)
+        } else {
+            Color::srgb(0.3, 0.6, 1.)
+        };
+        gizmos.circle_2d(position, 70., color);
+        gizmos.cross_2d(position, 30., color);
+    }
+
+    for (i, pickup) in map.pickups.iter().enumerate() {
+        let position = Vec2::new(pickup.position.0, pickup.position.1);
+        let color = if editor.selection == Some(Selection::Pickup(i)) {
+            Color::srgb(1., 0.9, 0.2)
+        } else {
+            pickup.kind.color()
+        };
+        gizmos.circle_2d(position, 40., color);
+    }
+
+    if let (Some(Drag::Create { start }), Some(cursor)) = (editor.drag, cursor.world()) {
+        let rect = Rect::from_corners(start, editor.snap(cursor));
+        gizmos.rect_2d(rect.center(), rect.size(), Color::srgb(0.2, 1., 0.4));
+    }
+}
+
+fn update_editor_hud(
+    editor: Res<Editor>,
+    current_map: Res<CurrentMap>,
+    mut hud_q: Query<&mut Text, With<EditorHud>>,
+) {
+    let tool = match editor.tool {
+        EditorTool::Block => String::from("blocks"),
+        EditorTool::SpawnPoint => String::from("spawn points"),
+        EditorTool::Pickup => format!("pickups ({:?}, Q to change)", editor.pickup_kind),
+    };
+
+    for mut text in hud_q.iter_mut() {
+        text.0 = format!(
+            "EDITOR - {}\n\
+             [F1] play  [1] blocks  [2] spawn points  [3] pickups  [WASD] pan  [wheel] zoom\n\
+             [LMB] place / drag / resize corners  [RMB] or [Del] delete  [G] snap: {}  [R] rotate  [H] health\n\
+             [Ctrl+Z] undo ({})  [Ctrl+Y] redo ({})  [Ctrl+S] save\n\
+             tool: {}\n\
+             {}",
+            current_map.path,
+            if editor.snap { "on" } else { "off" },
+            editor.undo.len(),
+            editor.redo.len(),
+            tool,
+            editor.status,
+        );
+    }
+}
@@ -1,22 +1,23 @@
 use crate::{
     colliders::colliders_o::{self, ColliderType},
-    player::{Barrel, Player, PlayerHealthBar},
+    health::{spawn_health_bar, Health},
+    movement::{Momentum, MovementState},
+    player::{PLAYER_MAX_HEALTH, PLAYER_RADIUS},
+    weapons::{barrel_mesh, BarrelShape, WeaponKind},
 };
 use bevy::prelude::*;
 
 #[derive(Component, Clone)]
 pub struct EnemyPlayer {
     pub position: (f32, f32),
-}
-
-#[derive(Component)]
-pub struct EnemyPlayerHealthBar {
-    pub current_health: f32,
+    // What its owner last said, so the death animation and blinking start and stop once.
+    pub dead: bool,
+    pub protected: bool,
 }
 
 #[derive(Component)]
 pub struct EnemyBarrel {
-    angle: f32,
+    pub angle: f32,
 }
 
 impl EnemyPlayer {
@@ -25,13 +26,24 @@ impl EnemyPlayer {
         meshes: &mut ResMut<Assets<Mesh>>,
         materials: &mut ResMut<Assets<ColorMaterial>>,
         position: (f32, f32),
-    ) {
-        let player = EnemyPlayer { position };
+    ) -> Entity {
+        let player = EnemyPlayer {
+            position,
+            dead: false,
+            protected: false,
+        };
         let clone = player.clone();
 
         let player = commands
             .spawn((
                 player,
+                Health::new(PLAYER_MAX_HEALTH),
+                Momentum::default(),
+                MovementState::default(),
+                colliders_o::CircleCollider {
+                    collider_type: ColliderType::Enemy,
+                    radius: PLAYER_RADIUS,
+                },
                 Mesh2d(meshes.add(Circle::new(70.))),
                 MeshMaterial2d(materials.add(Color::hsla(0., 1., 0.27, 1.))),
                 Transform::from_xyz(clone.position.0, clone.position.1, 2.0),
@@ -41,42 +53,23 @@ impl EnemyPlayer {
         commands
             .spawn((
                 EnemyBarrel { angle: 0.0 },
-                Mesh2d(meshes.add(Rectangle::new(80., 60.))),
+                BarrelShape(WeaponKind::default()),
+                Mesh2d(meshes.add(barrel_mesh(WeaponKind::default()))),
                 MeshMaterial2d(materials.add(Color::hsla(0., 1., 0.27, 1.))),
                 Transform::from_xyz(70.0, 0.0, 2.0),
             ))
             .set_parent(player);
 
-        commands
-            .spawn((
-                EnemyPlayerHealthBar {
-                    current_health: 100.0,
-                },
-                Mesh2d(meshes.add(Rectangle::new(130., 25.))),
-                MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                Transform::from_xyz(0., -110., 5.0),
-            ))
-            .set_parent(player);
+        spawn_health_bar(commands, meshes, materials, player);
 
-        commands
-            .spawn((
-                Mesh2d(meshes.add(Rectangle::new(135., 30.))),
-                MeshMaterial2d(materials.add(Color::hsl(159., 0.92, 0.10))),
-                Transform::from_xyz(0., -110., 4.0),
-            ))
-            .set_parent(player);
+        player
     }
 }
 
-
-pub fn rotate_enemy_barrel(
-    query: Query<&mut Transform, With<EnemyBarrel>>,
-    barrel: Query<(Entity, &EnemyBarrel)>,
-) {
-    let mut binding = query;
-    let mut barrel_transform = binding.single_mut();
-    let (_, enemy_barrel) = barrel.single();
-    let angle = enemy_barrel.angle;
-    barrel_transform.rotation = Quat::from_rotation_z(angle);
-    barrel_transform.translation = Vec3::new(angle.cos() * 70., angle.sin() * 70., 0.0);
+pub fn rotate_enemy_barrel(mut query: Query<(&mut Transform, &EnemyBarrel)>) {
+    for (mut barrel_transform, enemy_barrel) in query.iter_mut() {
+        let angle = enemy_barrel.angle;
+        barrel_transform.rotation = Quat::from_rotation_z(angle);
+        barrel_transform.translation = Vec3::new(angle.cos() * 70., angle.sin() * 70., 0.0);
+    }
 }
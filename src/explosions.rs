@@ -0,0 +1,344 @@
+use bevy::prelude::*;
+
+use crate::{
+    ai::AiController,
+    audio::audioO::ExplosionSoundtrackPlayer,
+    broadphase::BlockBroadphase,
+    bullets::{
+        bullet_impacts, move_bullets, Bullet, FiredBy, FriendlyFire, LocalShot, Velocity, AI_OWNER,
+    },
+    colliders::colliders_o::{
+        circle_polygon_push, detect_bullet_collisions, segment_hits_block, BlockCollider,
+        CollisionStarted,
+    },
+    death::{Dead, SpawnProtection},
+    destruction::{BlockHit, Destructible},
+    editor::GameMode,
+    health::Health,
+    map::MapState,
+    movement::{HitStun, Momentum},
+    pickups::PowerUps,
+    player::{Player, PLAYER_RADIUS},
+};
+
+const FLASH_SECONDS: f32 = 0.4;
+// Blasts start this far off whatever set them off, so that wall doesn't count as
+// standing between the blast and everything on its near side.
+const BLAST_LIFT: f32 = 12.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExplosiveStats {
+    pub radius: f32,
+    // Damage at the center, dropping to nothing at the edge.
+    pub damage: i32,
+    // How far someone at the center gets thrown, and how long they're slowed after.
+    pub knockback: f32,
+    pub stun_secs: f32,
+    // Goes off on its own after this long if it hasn't hit anything.
+    pub fuse_secs: f32,
+    // Fraction of its speed lost each second.
+    pub drag: f32,
+    // Thrown in an arc instead of flying flat. Only the looks change.
+    pub lobbed: bool,
+}
+
+// A projectile that blows up when it hits something or its fuse runs out.
+#[derive(Component)]
+pub struct Explosive {
+    pub stats: ExplosiveStats,
+    fuse: Timer,
+}
+
+impl Explosive {
+    pub fn new(stats: ExplosiveStats) -> Self {
+        Explosive {
+            stats,
+            fuse: Timer::from_seconds(stats.fuse_secs, TimerMode::Once),
+        }
+    }
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct Explosion {
+    pub position: Vec2,
+    pub stats: ExplosiveStats,
+    pub fired_by: FiredBy,
+    // Set off by the local player's own shot, so its block damage is theirs to report.
+    pub local: bool,
+}
+
+#[derive(Component)]
+pub struct ExplosionFlash {
+    lifetime: Timer,
+}
+
+pub struct ExplosionPlugin;
+
+impl Plugin for ExplosionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Explosion>().add_systems(
+            Update,
+            (
+                // Anything whose fuse ran out is gone before it can hit something too.
+                tick_fuses.before(move_bullets),
+                // Has to see the projectile before `bullet_impacts` despawns it.
+                detonate_on_impact
+                    .after(detect_bullet_collisions)
+                    .before(bullet_impacts),
+                (apply_explosions, blast_blocks, show_explosions)
+                    .after(tick_fuses)
+                    .after(detonate_on_impact),
+                fade_explosion_flashes,
+            )
+                .run_if(in_state(MapState::Ready).and(in_state(GameMode::Play))),
+        );
+    }
+}
+
+// Explosives still in flight or on the ground, counting down.
+type FuseQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Explosive,
+        &'static mut Velocity,
+        &'static mut Transform,
+        &'static Bullet,
+        &'static FiredBy,
+        Has<LocalShot>,
+    ),
+>;
+
+pub fn tick_fuses(
+    mut commands: Commands,
+    mut explosive_q: FuseQuery,
+    mut explosions: EventWriter<Explosion>,
+    time: Res<Time>,
+) {
+    for (entity, mut explosive, mut velocity, mut transform, bullet, fired_by, local) in
+        explosive_q.iter_mut()
+    {
+        explosive.fuse.tick(time.delta());
+        if explosive.fuse.finished() {
+            explosions.send(Explosion {
+                position: Vec2::from(bullet.position),
+                stats: explosive.stats,
+                fired_by: fired_by.clone(),
+                local,
+            });
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let slowdown = (1. - explosive.stats.drag * time.delta_secs()).max(0.);
+        velocity.x *= slowdown;
+        velocity.y *= slowdown;
+
+        // Top down, an arc is the projectile growing as it rises and shrinking as it
+        // comes back to the ground.
+        if explosive.stats.lobbed {
+            let t = (explosive.fuse.fraction() * 2.).min(1.);
+            transform.scale = Vec3::splat(1. + 0.8 * (t * std::f32::consts::PI).sin());
+        }
+    }
+}
+
+pub fn detonate_on_impact(
+    mut collisions: EventReader<CollisionStarted>,
+    explosive_q: Query<(&Explosive, &FiredBy, Has<LocalShot>)>,
+    mut explosions: EventWriter<Explosion>,
+) {
+    for collision in collisions.read() {
+        let Ok((explosive, fired_by, local)) = explosive_q.get(collision.a) else {
+            continue;
+        };
+        explosions.send(Explosion {
+            position: collision.point + collision.normal * BLAST_LIFT,
+            stats: explosive.stats,
+            fired_by: fired_by.clone(),
+            local,
+        });
+    }
+}
+
+// Anyone a blast can still hurt.
+type VictimQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static mut Health,
+        &'static mut Momentum,
+        Option<&'static Player>,
+        Option<&'static PowerUps>,
+        Has<AiController>,
+    ),
+    (Without<Dead>, Without<SpawnProtection>, Without<Bullet>),
+>;
+
+// Damage and knockback fall off from the center to the edge of the blast, and walls
+// shield whoever is behind them. Remote players are left to their own clients.
+pub fn apply_explosions(
+    mut commands: Commands,
+    mut explosions: EventReader<Explosion>,
+    mut victim_q: VictimQuery,
+    block_q: Query<&BlockCollider>,
+    broadphase: Res<BlockBroadphase>,
+    friendly_fire: Res<FriendlyFire>,
+) {
+    let mut candidates = Vec::new();
+    for explosion in explosions.read() {
+        let stats = explosion.stats;
+        for (entity, transform, mut health, mut momentum, player, power_ups, ai) in
+            victim_q.iter_mut()
+        {
+            let victim = match (player, ai) {
+                (Some(player), _) => player.id.as_str(),
+                (None, true) => AI_OWNER,
+                (None, false) => continue,
+            };
+            if health.is_dead() || !explosion.fired_by.can_hurt(victim, &friendly_fire) {
+                continue;
+            }
+
+            let position = transform.translation.truncate();
+            let offset = position - explosion.position;
+            let distance = (offset.length() - PLAYER_RADIUS).max(0.);
+            if distance >= stats.radius {
+                continue;
+            }
+
+            broadphase.query(
+                explosion.position.min(position),
+                explosion.position.max(position),
+                &mut candidates,
+            );
+            let shielded = candidates.iter().any(|&block| {
+                block_q
+                    .get(block)
+                    .is_ok_and(|block| segment_hits_block(explosion.position, position, block))
+            });
+            if shielded {
+                continue;
+            }
+
+            let strength = 1. - distance / stats.radius;
+            let damage = (stats.damage as f32 * strength).round() as i32;
+            health.current -= power_ups.map_or(damage, |power_ups| power_ups.damage_taken(damage));
+            // Thrown through their movement, so walls stop them like any other move would.
+            momentum.shove(
+                offset.try_normalize().unwrap_or(Vec2::X),
+                stats.knockback * strength,
+            );
+            if stats.stun_secs > 0. {
+                commands
+                    .entity(entity)
+                    .insert(HitStun::new(stats.stun_secs));
+            }
+
+            if player.is_some() && health.is_dead() {
+                commands
+                    .entity(entity)
+                    .insert(Dead::new(Some(explosion.fired_by.entity)));
+            }
+        }
+    }
+}
+
+// Destructible blocks in the radius take damage the same way players do, falling off
+// with distance from the blast to their nearest face.
+pub fn blast_blocks(
+    mut explosions: EventReader<Explosion>,
+    block_q: Query<(&BlockCollider, &Destructible)>,
+    broadphase: Res<BlockBroadphase>,
+    mut block_hits: EventWriter<BlockHit>,
+) {
+    let mut candidates = Vec::new();
+    for explosion in explosions.read() {
+        let stats = explosion.stats;
+        broadphase.query_circle(explosion.position, stats.radius, &mut candidates);
+        for &block in &candidates {
+            let Ok((collider, destructible)) = block_q.get(block) else {
+                continue;
+            };
+            // The push out of the block is however far the blast reaches into it.
+            let Some(push) =
+                circle_polygon_push(explosion.position, stats.radius, &collider.points)
+            else {
+                continue;
+            };
+            let strength = (push.length() / stats.radius).min(1.);
+            let damage = (stats.damage as f32 * strength).round() as i32;
+            if damage <= 0 {
+                continue;
+            }
+            // Chips fly off the face nearest the blast.
+            let distance = (stats.radius - push.length()).max(0.);
+            block_hits.send(BlockHit {
+                index: destructible.index,
+                damage,
+                position: explosion.position - push.normalize_or_zero() * distance,
+                local: explosion.local,
+            });
+        }
+    }
+}
+
+pub fn show_explosions(
+    mut commands: Commands,
+    mut explosions: EventReader<Explosion>,
+    player_q: Query<&Transform, With<Player>>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let player_pos = player_q
+        .get_single()
+        .map(|transform| transform.translation.truncate())
+        .unwrap_or_default();
+    let sound = ExplosionSoundtrackPlayer::new("sounds/impact.ogg", &asset_server);
+
+    for explosion in explosions.read() {
+        let volume = 0.8 - (player_pos.distance(explosion.position) / 8000.).min(0.6);
+        ExplosionSoundtrackPlayer::play_explosion_sound(&sound, &mut commands, volume);
+
+        commands.spawn((
+            ExplosionFlash {
+                lifetime: Timer::from_seconds(FLASH_SECONDS, TimerMode::Once),
+            },
+            Mesh2d(meshes.add(Circle::new(explosion.stats.radius))),
+            MeshMaterial2d(materials.add(Color::hsla(30., 1., 0.55, 0.8))),
+            Transform::from_xyz(explosion.position.x, explosion.position.y, 6.0)
+                .with_scale(Vec3::splat(0.3)),
+        ));
+    }
+}
+
+pub fn fade_explosion_flashes(
+    mut commands: Commands,
+    mut flash_q: Query<(
+        Entity,
+        &mut ExplosionFlash,
+        &mut Transform,
+        &MeshMaterial2d<ColorMaterial>,
+    )>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time>,
+) {
+    for (entity, mut flash, mut transform, material) in flash_q.iter_mut() {
+        flash.lifetime.tick(time.delta());
+        if flash.lifetime.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        // Bursts out to the full radius while fading away.
+        let t = flash.lifetime.fraction();
+        transform.scale = Vec3::splat(0.3 + 0.7 * t.sqrt());
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color.set_alpha(0.8 * (1. - t));
+        }
+    }
+}
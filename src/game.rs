@@ -1,12 +1,19 @@
-use bevy::state::commands;
-use bevy::window::WindowMode;
 use bevy::{math::*, prelude::*, window::WindowResolution};
 
-use crate::audio::audioO::*;
+use crate::broadphase::{update_body_broadphase, BroadphasePlugin};
 use crate::colliders::colliders_o::*;
-use crate::enemy::EnemyPlayer;
-use crate::{block, bullets, colliders, enemy, player};
-use block::*;
+use crate::death::{player_alive, DeathPlugin};
+use crate::destruction::DestructionPlugin;
+use crate::editor::GameMode;
+use crate::explosions::ExplosionPlugin;
+use crate::health::HealthPlugin;
+use crate::hits::HitMarkerPlugin;
+use crate::map::MapState;
+use crate::movement::MovementPlugin;
+use crate::navigation::NavigationPlugin;
+use crate::pickups::PickupPlugin;
+use crate::weapons::WeaponPlugin;
+use crate::{bullets, colliders, enemy, player};
 use bullets::*;
 use colliders::colliders_o;
 use player::*;
@@ -18,26 +25,39 @@ impl Plugin for UpdatesPlugin {
         app.add_systems(
             Update,
             (
-                player::rotate_barrel,
-                player::player_movement.before(detect_block_and_circle_collision),
-                bullets::shoot,
+                player::rotate_barrel.run_if(player_alive),
+                player::player_movement
+                    .before(detect_block_and_circle_collision)
+                    .run_if(player_alive),
+                bullets::shoot.run_if(player_alive),
                 bullets::move_bullets
                     .after(shoot)
-                    .before(detect_block_and_bullet_collision),
-                colliders_o::detect_block_and_bullet_collision.after(move_bullets),
-                colliders_o::detect_block_and_circle_collision.after(player_movement),
-                colliders_o::detect_player_and_bullet_collision,
+                    .before(detect_bullet_collisions),
+                // Bodies are hashed where they ended up moving, before anything is
+                // checked against them.
+                update_body_broadphase
+                    .after(player_movement)
+                    .before(detect_bullet_collisions)
+                    .before(detect_circle_and_circle_collision),
+                colliders_o::detect_bullet_collisions.after(move_bullets),
+                colliders_o::detect_circle_and_circle_collision.after(player_movement),
+                // Walls get the last word, so being shoved never pushes anyone into one.
+                colliders_o::detect_block_and_circle_collision
+                    .after(detect_circle_and_circle_collision),
+                player::take_bullet_damage
+                    .after(detect_bullet_collisions)
+                    .before(bullet_impacts),
+                bullets::bullet_impacts.after(detect_bullet_collisions),
                 enemy::rotate_enemy_barrel,
-                player::update_player_health,
-            ),
+                player::follow_player_camera.after(detect_block_and_circle_collision),
+            )
+                .run_if(in_state(MapState::Ready).and(in_state(GameMode::Play))),
         );
     }
 }
 
 pub const SCREEN_HEIGHT: f32 = 1600.0;
 pub const SCREEN_WIDTH: f32 = 2560.0;
-pub const MAP_HEIGHT: f32 = 20000.;
-pub const MAP_WIDTH: f32 = 20000.;
 
 pub struct Game;
 
@@ -57,89 +77,23 @@ impl Plugin for Game {
                 ..default()
             }),
         )
+        .init_resource::<FriendlyFire>()
+        .add_plugins(CollidersPlugin)
         .add_plugins(UpdatesPlugin)
+        .add_plugins(NavigationPlugin)
+        .add_plugins(BroadphasePlugin)
+        .add_plugins(DestructionPlugin)
+        .add_plugins(HitMarkerPlugin)
+        .add_plugins(HealthPlugin)
+        .add_plugins(DeathPlugin)
+        .add_plugins(WeaponPlugin)
+        .add_plugins(ExplosionPlugin)
+        .add_plugins(PickupPlugin)
+        .add_plugins(MovementPlugin);
         // .add_plugins(DefaultPlugins)
         // .add_plugins(FrameTimeDiagnosticsPlugin)
         // .add_plugins(EntityCountDiagnosticsPlugin::default())
         // .add_plugins(SystemInformationDiagnosticsPlugin)
         // .add_plugins(PerfUiPlugin)
-        .add_systems(Startup, setup);
     }
 }
-
-fn setup(
-    asset_server: Res<AssetServer>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
-    Block::spawn_block(
-        Block {
-            points: (
-                (-5000., -4900.),
-                (5000., -4900.),
-                (5000., -5000.),
-                (-5000., -5000.),
-            ),
-        },
-        &mut commands,
-        &mut meshes,
-        &mut materials,
-    );
-    Block::spawn_block(
-        Block {
-            points: (
-                (-5000., 5000.),
-                (5000., 5000.),
-                (5000., 4900.),
-                (-5000., 4900.),
-            ),
-        },
-        &mut commands,
-        &mut meshes,
-        &mut materials,
-    );
-    Block::spawn_block(
-        Block {
-            points: (
-                (-5000., 5000.),
-                (-4900., 5000.),
-                (-4900., -5000.),
-                (-5000., -5000.),
-            ),
-        },
-        &mut commands,
-        &mut meshes,
-        &mut materials,
-    );
-    Block::spawn_block(
-        Block {
-            points: (
-                (5000., 4900.),
-                (5000., 5000.),
-                (5000., -5000.),
-                (4900., -5000.),
-            ),
-        },
-        &mut commands,
-        &mut meshes,
-        &mut materials,
-    );
-
-    // let health = EHealth::new(100.);
-    // let enemy = Enemy::new(health);
-    // Enemy::spawn(enemy, &mut commands, &mut meshes, &mut materials);
-
-    Block::spawn_block(
-        Block {
-            points: ((100., 500.), (1000., 500.), (1000., 100.), (100., 100.)),
-        },
-        &mut commands,
-        &mut meshes,
-        &mut materials,
-    );
-
-    let track = "sounds/backmusic.ogg";
-    let stp = SoundtrackPlayer::new(track, asset_server);
-    SoundtrackPlayer::play_track(stp, commands);
-}
@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+
+const BAR_WIDTH: f32 = 130.;
+const BAR_HEIGHT: f32 = 25.;
+const BAR_OFFSET: f32 = -110.;
+
+// Health of anything that can be shot: the local player, remote players and AI.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32,
+}
+
+impl Health {
+    pub fn new(max: i32) -> Self {
+        Health { current: max, max }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        (self.current as f32 / self.max.max(1) as f32).clamp(0., 1.)
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0
+    }
+}
+
+// The filled part of the bar under an entity with `Health`. It's a child of that
+// entity, with its own material so its color can change on its own.
+#[derive(Component)]
+pub struct HealthBar;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, update_health_bars);
+    }
+}
+
+// Gives `owner` a full health bar on a dark background.
+pub fn spawn_health_bar(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    owner: Entity,
+) {
+    commands
+        .spawn((
+            HealthBar,
+            Mesh2d(meshes.add(Rectangle::new(BAR_WIDTH, BAR_HEIGHT))),
+            MeshMaterial2d(materials.add(health_color(1.))),
+            Transform::from_xyz(0., BAR_OFFSET, 5.0),
+        ))
+        .set_parent(owner);
+
+    commands
+        .spawn((
+            Mesh2d(meshes.add(Rectangle::new(BAR_WIDTH + 5., BAR_HEIGHT + 5.))),
+            MeshMaterial2d(materials.add(Color::hsl(159., 0.92, 0.10))),
+            Transform::from_xyz(0., BAR_OFFSET, 4.0),
+        ))
+        .set_parent(owner);
+}
+
+// Green when full, through yellow, to red when empty.
+pub fn health_color(fraction: f32) -> Color {
+    Color::hsl(120. * fraction, 0.9, 0.35)
+}
+
+// Shrinks each bar towards its left end in proportion to its owner's health.
+pub fn update_health_bars(
+    owner_q: Query<(&Health, &Children), Changed<Health>>,
+    mut bar_q: Query<(&mut Transform, &MeshMaterial2d<ColorMaterial>), With<HealthBar>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for (health, children) in owner_q.iter() {
+        let fraction = health.fraction();
+        for &child in children.iter() {
+            let Ok((mut transform, material)) = bar_q.get_mut(child) else {
+                continue;
+            };
+            transform.scale.x = fraction;
+            transform.translation.x = -BAR_WIDTH * (1. - fraction) / 2.;
+            if let Some(material) = materials.get_mut(&material.0) {
+                material.color = health_color(fraction);
+            }
+        }
+    }
+}
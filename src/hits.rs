@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+
+use crate::{
+    audio::audioO::HitConfirmSoundtrackPlayer,
+    bullets::{bullet_impacts, LocalShot},
+    colliders::colliders_o::{detect_bullet_collisions, CollisionStarted},
+    editor::GameMode,
+    enemy::EnemyPlayer,
+    map::MapState,
+};
+
+const HIT_MARKER_SECONDS: f32 = 0.3;
+
+// A cross flashed where one of the local player's shots hit another player. It's only
+// feedback: the damage itself is up to whoever owns the player that was hit.
+#[derive(Component)]
+pub struct HitMarker {
+    lifetime: Timer,
+}
+
+pub struct HitMarkerPlugin;
+
+impl Plugin for HitMarkerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                // Has to see the bullet before `bullet_impacts` despawns it.
+                confirm_hits
+                    .after(detect_bullet_collisions)
+                    .before(bullet_impacts),
+                fade_hit_markers,
+            )
+                .run_if(in_state(MapState::Ready).and(in_state(GameMode::Play))),
+        );
+    }
+}
+
+pub fn confirm_hits(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionStarted>,
+    shot_q: Query<(), With<LocalShot>>,
+    enemy_q: Query<(), With<EnemyPlayer>>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let hits: Vec<Vec2> = collisions
+        .read()
+        .filter(|collision| shot_q.contains(collision.a) && enemy_q.contains(collision.b))
+        .map(|collision| collision.point)
+        .collect();
+    if hits.is_empty() {
+        return;
+    }
+
+    let sound = HitConfirmSoundtrackPlayer::new("sounds/struck.ogg", &asset_server);
+    let bar = meshes.add(Rectangle::new(44., 8.));
+    let material = materials.add(ColorMaterial::from(Color::WHITE));
+
+    for point in hits {
+        HitConfirmSoundtrackPlayer::play_hit_sound(&sound, &mut commands);
+        commands
+            .spawn((
+                HitMarker {
+                    lifetime: Timer::from_seconds(HIT_MARKER_SECONDS, TimerMode::Once),
+                },
+                Transform::from_xyz(point.x, point.y, 6.0)
+                    .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)),
+                Visibility::default(),
+            ))
+            .with_children(|marker| {
+                marker.spawn((Mesh2d(bar.clone()), MeshMaterial2d(material.clone())));
+                marker.spawn((
+                    Mesh2d(bar.clone()),
+                    MeshMaterial2d(material.clone()),
+                    Transform::from_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2)),
+                ));
+            });
+    }
+}
+
+pub fn fade_hit_markers(
+    mut commands: Commands,
+    mut marker_q: Query<(Entity, &mut HitMarker, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (entity, mut marker, mut transform) in marker_q.iter_mut() {
+        marker.lifetime.tick(time.delta());
+        if marker.lifetime.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        // Pops in large and shrinks away.
+        let remaining = marker.lifetime.fraction_remaining();
+        transform.scale = Vec3::splat(0.6 + 0.6 * remaining);
+    }
+}
@@ -0,0 +1,24 @@
+// Everything the client, server and bot binaries share, so it's compiled once and can
+// be benchmarked and tested on its own.
+pub mod ai;
+pub mod audio;
+pub mod block;
+pub mod broadphase;
+pub mod bullets;
+pub mod colliders;
+pub mod death;
+pub mod destruction;
+pub mod editor;
+pub mod enemy;
+pub mod explosions;
+pub mod game;
+pub mod health;
+pub mod hits;
+pub mod map;
+pub mod mapgen;
+pub mod movement;
+pub mod navigation;
+pub mod net;
+pub mod pickups;
+pub mod player;
+pub mod weapons;
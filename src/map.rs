@@ -0,0 +1,477 @@
+use std::fmt;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    ecs::system::SystemParam,
+    prelude::*,
+};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::audioO::SoundtrackPlayer,
+    block::Block,
+    destruction::{BlockHealthLog, Destructible},
+    mapgen::{self, GeneratorParams, Layout},
+    pickups::spawn_pickup,
+};
+
+pub const DEFAULT_MAP: &str = "maps/default.map.json";
+pub const BOUNDARY_WALL_THICKNESS: f32 = 100.;
+// Respawning any closer than this to an enemy isn't safe.
+pub const SAFE_SPAWN_DISTANCE: f32 = 1500.;
+
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone)]
+pub struct MapData {
+    pub name: String,
+    pub bounds: MapBounds,
+    pub background_color: (f32, f32, f32),
+    #[serde(default)]
+    pub music: Option<String>,
+    #[serde(default)]
+    pub walls: Vec<WallData>,
+    #[serde(default)]
+    pub spawn_points: Vec<(f32, f32)>,
+    #[serde(default)]
+    pub pickups: Vec<PickupData>,
+}
+
+// Outer size of the map. The boundary walls sit just inside it, and everything else
+// (movement, bullets, the camera and navigation) is limited by it.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MapBounds {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for MapBounds {
+    fn default() -> Self {
+        MapBounds {
+            width: 10000.,
+            height: 10000.,
+        }
+    }
+}
+
+impl MapBounds {
+    pub fn half_extents(&self) -> Vec2 {
+        Vec2::new(self.width, self.height) / 2.
+    }
+
+    pub fn min(&self) -> Vec2 {
+        -self.half_extents()
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.half_extents()
+    }
+
+    // Area inside the boundary walls.
+    pub fn playable_half_extents(&self) -> Vec2 {
+        self.half_extents() - Vec2::splat(BOUNDARY_WALL_THICKNESS)
+    }
+
+    pub fn contains(&self, position: Vec2) -> bool {
+        let half = self.half_extents();
+        position.x.abs() <= half.x && position.y.abs() <= half.y
+    }
+
+    pub fn boundary_walls(&self) -> [Block; 4] {
+        let outer = self.half_extents();
+        let inner = self.playable_half_extents();
+
+        [
+            Block::from_rect(Vec2::new(-outer.x, -outer.y), Vec2::new(outer.x, -inner.y)),
+            Block::from_rect(Vec2::new(-outer.x, inner.y), Vec2::new(outer.x, outer.y)),
+            Block::from_rect(Vec2::new(-outer.x, -outer.y), Vec2::new(-inner.x, outer.y)),
+            Block::from_rect(Vec2::new(inner.x, -outer.y), Vec2::new(outer.x, outer.y)),
+        ]
+    }
+}
+
+// Any number of points; the wall is their convex hull, turned by `rotation` degrees
+// around its center. Walls with health can be shot down.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WallData {
+    pub points: Vec<(f32, f32)>,
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub health: Option<i32>,
+}
+
+impl WallData {
+    pub fn from_rect(min: Vec2, max: Vec2) -> Self {
+        WallData {
+            points: Block::from_rect(min, max)
+                .points
+                .iter()
+                .map(|point| (point.x, point.y))
+                .collect(),
+            rotation: 0.,
+            health: None,
+        }
+    }
+
+    pub fn block(&self) -> Block {
+        Block::from_points(&self.points, self.rotation.to_radians())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PickupKind {
+    Health,
+    Ammo,
+    SpeedBoost,
+    DamageBoost,
+    Shield,
+}
+
+impl PickupKind {
+    pub fn color(self) -> Color {
+        match self {
+            PickupKind::Health => Color::srgb(0.2, 0.9, 0.2),
+            PickupKind::Ammo => Color::srgb(0.9, 0.8, 0.2),
+            PickupKind::SpeedBoost => Color::srgb(0.2, 0.8, 0.9),
+            PickupKind::DamageBoost => Color::srgb(0.9, 0.3, 0.2),
+            PickupKind::Shield => Color::srgb(0.5, 0.4, 0.9),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PickupKind::Health => "Health",
+            PickupKind::Ammo => "Ammo",
+            PickupKind::SpeedBoost => "Speed boost",
+            PickupKind::DamageBoost => "Damage boost",
+            PickupKind::Shield => "Shield",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PickupData {
+    pub kind: PickupKind,
+    pub position: (f32, f32),
+    #[serde(default = "default_respawn_secs")]
+    pub respawn_secs: f32,
+}
+
+fn default_respawn_secs() -> f32 {
+    20.
+}
+
+impl MapData {
+    // For the server and bots, which read maps straight from disk instead of going
+    // through the asset server.
+    pub fn load_from_file(path: &str) -> Result<Self, MapLoadError> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = Block> + '_ {
+        self.walls
+            .iter()
+            .map(WallData::block)
+            .chain(self.bounds.boundary_walls())
+    }
+
+    // A random spawn point with no enemy nearby, or the one farthest from any enemy if
+    // none are safe. Maps without spawn points fall back to the center.
+    pub fn pick_spawn(&self, enemies: &[Vec2]) -> (f32, f32) {
+        let nearest_enemy = |point: &(f32, f32)| {
+            enemies
+                .iter()
+                .map(|enemy| enemy.distance(Vec2::new(point.0, point.1)))
+                .fold(f32::MAX, f32::min)
+        };
+
+        let safe: Vec<(f32, f32)> = self
+            .spawn_points
+            .iter()
+            .copied()
+            .filter(|point| nearest_enemy(point) >= SAFE_SPAWN_DISTANCE)
+            .collect();
+        if let Some(&point) = safe.choose(&mut rand::thread_rng()) {
+            return point;
+        }
+
+        self.spawn_points
+            .iter()
+            .copied()
+            .max_by(|a, b| nearest_enemy(a).total_cmp(&nearest_enemy(b)))
+            .unwrap_or((0., 0.))
+    }
+}
+
+// Where a map comes from. The server sends its source to every client that joins, so
+// generated maps only need the seed to come out the same everywhere.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MapSource {
+    File(String),
+    Generated { seed: u64, params: GeneratorParams },
+}
+
+impl MapSource {
+    // `--map path` for a map file, or `--seed n` (with optional `--layout rooms|scatter`,
+    // `--asymmetric` and `--spawns n`) for a generated one.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+        };
+
+        let Some(seed) = value("--seed") else {
+            let path = value("--map").cloned();
+            return Ok(MapSource::File(
+                path.unwrap_or_else(|| String::from(DEFAULT_MAP)),
+            ));
+        };
+        let seed = seed.parse().map_err(|_| "--seed expects a number")?;
+
+        let mut params = GeneratorParams::default();
+        if let Some(layout) = value("--layout") {
+            params.layout = Layout::from_arg(layout).ok_or("--layout expects rooms or scatter")?;
+        }
+        if let Some(spawns) = value("--spawns") {
+            params.spawn_count = spawns.parse().map_err(|_| "--spawns expects a number")?;
+        }
+        if args.iter().any(|arg| arg == "--asymmetric") {
+            params.symmetric = false;
+        }
+
+        Ok(MapSource::Generated { seed, params })
+    }
+
+    // Asset path of the map. Generated maps get one too, so the editor can save them.
+    pub fn path(&self) -> String {
+        match self {
+            MapSource::File(path) => path.clone(),
+            MapSource::Generated { seed, .. } => format!("maps/generated-{}.map.json", seed),
+        }
+    }
+
+    pub fn load(&self) -> Result<MapData, MapLoadError> {
+        match self {
+            MapSource::File(path) => MapData::load_from_file(&format!("assets/{}", path)),
+            MapSource::Generated { seed, params } => Ok(mapgen::generate(*seed, params)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapLoadError::Io(e) => write!(f, "couldn't read map file: {}", e),
+            MapLoadError::Json(e) => write!(f, "couldn't parse map file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MapLoadError {}
+
+impl From<std::io::Error> for MapLoadError {
+    fn from(e: std::io::Error) -> Self {
+        MapLoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapLoadError {
+    fn from(e: serde_json::Error) -> Self {
+        MapLoadError::Json(e)
+    }
+}
+
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    type Asset = MapData;
+    type Settings = ();
+    type Error = MapLoadError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<MapData, MapLoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.json"]
+    }
+}
+
+#[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapState {
+    #[default]
+    Loading,
+    Ready,
+}
+
+#[derive(Resource)]
+pub struct CurrentMap {
+    pub path: String,
+    pub handle: Handle<MapData>,
+}
+
+// Everything spawned from the map file, so it can be torn down on reload.
+#[derive(Component)]
+pub struct MapEntity;
+
+#[derive(Component)]
+pub struct MapMusic {
+    pub track: String,
+}
+
+pub struct MapPlugin {
+    pub source: MapSource,
+}
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        let source = self.source.clone();
+
+        app.init_resource::<MapBounds>()
+            .init_asset::<MapData>()
+            .init_asset_loader::<MapLoader>()
+            .init_state::<MapState>()
+            .add_systems(
+                Startup,
+                move |mut commands: Commands,
+                      asset_server: Res<AssetServer>,
+                      mut maps: ResMut<Assets<MapData>>| {
+                    let handle = match &source {
+                        MapSource::File(path) => asset_server.load(path.clone()),
+                        MapSource::Generated { seed, params } => {
+                            maps.add(mapgen::generate(*seed, params))
+                        }
+                    };
+                    commands.insert_resource(CurrentMap {
+                        path: source.path(),
+                        handle,
+                    });
+                },
+            )
+            .add_systems(Update, spawn_map);
+    }
+}
+
+// What `spawn_map` needs to clear away the last load and put the map in the world.
+#[derive(SystemParam)]
+pub struct MapSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+    asset_server: Res<'w, AssetServer>,
+    map_entities: Query<'w, 's, Entity, With<MapEntity>>,
+    music_q: Query<'w, 's, (Entity, &'static MapMusic)>,
+    block_health: Res<'w, BlockHealthLog>,
+}
+
+pub fn spawn_map(
+    mut events: EventReader<AssetEvent<MapData>>,
+    current_map: Res<CurrentMap>,
+    maps: Res<Assets<MapData>>,
+    state: Res<State<MapState>>,
+    mut next_state: ResMut<NextState<MapState>>,
+    mut spawner: MapSpawner,
+) {
+    let MapSpawner {
+        commands,
+        meshes,
+        materials,
+        asset_server,
+        map_entities,
+        music_q,
+        block_health,
+    } = &mut spawner;
+
+    let reload = events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            *id == current_map.handle.id()
+        }
+        // Generated maps are added straight to the assets instead of being loaded.
+        AssetEvent::Added { id } => {
+            *id == current_map.handle.id() && asset_server.get_path(*id).is_none()
+        }
+        _ => false,
+    });
+    if !reload {
+        return;
+    }
+
+    let Some(map) = maps.get(&current_map.handle) else {
+        return;
+    };
+    println!("Loaded map {} from {}", map.name, current_map.path);
+
+    for entity in map_entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for (index, block) in map.blocks().enumerate() {
+        let destructible = map
+            .walls
+            .get(index)
+            .and_then(|wall| wall.health)
+            .map(|health| {
+                let mut destructible = Destructible::new(index, health);
+                if let Some(&current) = block_health.0.get(&index) {
+                    destructible.health = current;
+                }
+                destructible
+            });
+        // Shot down before the reload, so it stays down.
+        if destructible
+            .as_ref()
+            .is_some_and(|destructible| destructible.health <= 0)
+        {
+            continue;
+        }
+
+        let entity = Block::spawn_block(block, commands, meshes, materials);
+        commands.entity(entity).insert(MapEntity);
+        if let Some(destructible) = destructible {
+            commands.entity(entity).insert(destructible);
+        }
+    }
+
+    for (index, pickup) in map.pickups.iter().enumerate() {
+        let entity = spawn_pickup(commands, meshes, materials, index, pickup);
+        commands.entity(entity).insert(MapEntity);
+    }
+
+    let (r, g, b) = map.background_color;
+    commands.insert_resource(ClearColor(Color::srgb(r, g, b)));
+    commands.insert_resource(map.bounds);
+
+    let playing = music_q.get_single().ok();
+    if playing.map(|(_, music)| &music.track) != map.music.as_ref() {
+        if let Some((entity, _)) = playing {
+            commands.entity(entity).despawn();
+        }
+        if let Some(track) = &map.music {
+            let stp = SoundtrackPlayer::new(track, Res::clone(asset_server));
+            let entity = SoundtrackPlayer::play_track(stp, commands.reborrow());
+            commands.entity(entity).insert(MapMusic {
+                track: track.clone(),
+            });
+        }
+    }
+
+    if *state.get() == MapState::Loading {
+        next_state.set(MapState::Ready);
+    }
+}
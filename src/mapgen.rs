@@ -0,0 +1,499 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::Block,
+    colliders::colliders_o::segment_hits_polygon,
+    map::{MapBounds, MapData, WallData},
+    navigation::{NavGrid, NAV_AGENT_RADIUS, NAV_CELL_SIZE},
+};
+
+const WALL_THICKNESS: f32 = 60.;
+const DOOR_WIDTH: f32 = 400.;
+// Cover stays this far from room walls, leaving a walkable ring that links every door.
+const ROOM_MARGIN: f32 = 300.;
+const SPAWN_CLEARANCE: f32 = 350.;
+const COVER_GAP: f32 = 200.;
+const EXTRA_DOOR_CHANCE: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    // A grid of rooms joined by doors, with cover inside the rooms.
+    Rooms,
+    // An open arena with cover scattered across it.
+    Scatter,
+}
+
+impl Layout {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg.to_lowercase().as_str() {
+            "rooms" => Some(Layout::Rooms),
+            "scatter" => Some(Layout::Scatter),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeneratorParams {
+    pub bounds: MapBounds,
+    pub layout: Layout,
+    // Mirror the left half onto the right so neither side has an advantage.
+    pub symmetric: bool,
+    pub spawn_count: usize,
+    pub room_size: f32,
+    // Cover blocks per 1000x1000 area.
+    pub cover_density: f32,
+}
+
+impl Default for GeneratorParams {
+    fn default() -> Self {
+        GeneratorParams {
+            bounds: MapBounds::default(),
+            layout: Layout::Rooms,
+            symmetric: true,
+            spawn_count: 6,
+            room_size: 2000.,
+            cover_density: 0.4,
+        }
+    }
+}
+
+struct Generator<'a> {
+    rng: StdRng,
+    params: &'a GeneratorParams,
+    // Where features are generated. Only the left half when the map is symmetric.
+    region: Rect,
+    walls: Vec<Rect>,
+    // Walls on the mirror line, which are their own mirror image.
+    center_walls: Vec<Rect>,
+    spawn_points: Vec<Vec2>,
+}
+
+// Builds the same map for the same seed and parameters, so the server only has to
+// hand out the seed.
+pub fn generate(seed: u64, params: &GeneratorParams) -> MapData {
+    let half = params.bounds.playable_half_extents();
+    let region = if params.symmetric {
+        Rect::from_corners(-half, Vec2::new(0., half.y))
+    } else {
+        Rect::from_corners(-half, half)
+    };
+
+    let mut generator = Generator {
+        rng: StdRng::seed_from_u64(seed),
+        params,
+        region,
+        walls: Vec::new(),
+        center_walls: Vec::new(),
+        spawn_points: Vec::new(),
+    };
+
+    match params.layout {
+        Layout::Rooms => generator.rooms(),
+        Layout::Scatter => generator.scatter(),
+    }
+    generator.mirror();
+    generator.connect_spawns();
+
+    MapData {
+        name: format!("Generated {}", seed),
+        bounds: params.bounds,
+        background_color: (0.12, 0.12, 0.14),
+        music: Some(String::from("sounds/backmusic.ogg")),
+        walls: generator
+            .walls
+            .iter()
+            .map(|wall| WallData::from_rect(wall.min, wall.max))
+            .collect(),
+        spawn_points: generator
+            .spawn_points
+            .iter()
+            .map(|spawn| (spawn.x, spawn.y))
+            .collect(),
+        pickups: Vec::new(),
+    }
+}
+
+impl Generator<'_> {
+    fn spawns_to_place(&self) -> usize {
+        if self.params.symmetric {
+            self.params.spawn_count.div_ceil(2)
+        } else {
+            self.params.spawn_count
+        }
+    }
+
+    fn rooms(&mut self) {
+        let half = self.params.bounds.playable_half_extents();
+        let mut cols = ((half.x * 2. / self.params.room_size).round() as usize).max(2);
+        if self.params.symmetric && cols % 2 == 1 {
+            cols += 1;
+        }
+        let rows = ((half.y * 2. / self.params.room_size).round() as usize).max(1);
+        let room = Vec2::new(half.x * 2. / cols as f32, half.y * 2. / rows as f32);
+        let gen_cols = if self.params.symmetric {
+            cols / 2
+        } else {
+            cols
+        };
+        let corner = |col: usize, row: usize| -half + room * Vec2::new(col as f32, row as f32);
+
+        let mut rooms: Vec<(usize, usize)> = (0..rows)
+            .flat_map(|row| (0..gen_cols).map(move |col| (col, row)))
+            .collect();
+        rooms.shuffle(&mut self.rng);
+        for &(col, row) in rooms.iter().cycle().take(self.spawns_to_place()) {
+            let center = corner(col, row) + room / 2.;
+            let jitter = room / 2. - Vec2::splat(ROOM_MARGIN + SPAWN_CLEARANCE);
+            let offset = Vec2::new(
+                self.rng.gen_range(-1.0..=1.0) * jitter.x.max(0.),
+                self.rng.gen_range(-1.0..=1.0) * jitter.y.max(0.),
+            );
+            self.spawn_points.push(center + offset);
+        }
+
+        // Random spanning tree over the rooms so every room is reachable, plus a few
+        // extra doors so there's more than one way around.
+        let index = |col: usize, row: usize| row * gen_cols + col;
+        let mut visited = vec![false; gen_cols * rows];
+        let mut doors = vec![[false; 2]; gen_cols * rows]; // [right, up]
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some(&(col, row)) = stack.last() {
+            let mut neighbours = Vec::new();
+            if col + 1 < gen_cols && !visited[index(col + 1, row)] {
+                neighbours.push((col + 1, row));
+            }
+            if col > 0 && !visited[index(col - 1, row)] {
+                neighbours.push((col - 1, row));
+            }
+            if row + 1 < rows && !visited[index(col, row + 1)] {
+                neighbours.push((col, row + 1));
+            }
+            if row > 0 && !visited[index(col, row - 1)] {
+                neighbours.push((col, row - 1));
+            }
+
+            let Some(&(next_col, next_row)) = neighbours.choose(&mut self.rng) else {
+                stack.pop();
+                continue;
+            };
+            if next_col != col {
+                doors[index(col.min(next_col), row)][0] = true;
+            } else {
+                doors[index(col, row.min(next_row))][1] = true;
+            }
+            visited[index(next_col, next_row)] = true;
+            stack.push((next_col, next_row));
+        }
+
+        for row in 0..rows {
+            for col in 0..gen_cols {
+                let min = corner(col, row);
+                let max = corner(col + 1, row + 1);
+
+                if col + 1 < gen_cols {
+                    let open = doors[index(col, row)][0] || self.rng.gen_bool(EXTRA_DOOR_CHANCE);
+                    self.wall_with_door(Vec2::new(max.x, min.y), Vec2::new(max.x, max.y), open);
+                }
+                if row + 1 < rows {
+                    let open = doors[index(col, row)][1] || self.rng.gen_bool(EXTRA_DOOR_CHANCE);
+                    self.wall_with_door(Vec2::new(min.x, max.y), Vec2::new(max.x, max.y), open);
+                }
+
+                self.room_cover(Rect::from_corners(min, max));
+            }
+        }
+
+        // The line between the two mirrored halves needs at least one door to join them.
+        if self.params.symmetric {
+            let always_open = self.rng.gen_range(0..rows);
+            for row in 0..rows {
+                let min = corner(gen_cols, row);
+                let max = corner(gen_cols, row + 1);
+                let open = row == always_open || self.rng.gen_bool(EXTRA_DOOR_CHANCE);
+                let walls = self.door_segments(min, max, open);
+                self.center_walls.extend(walls);
+            }
+        }
+    }
+
+    fn wall_with_door(&mut self, from: Vec2, to: Vec2, open: bool) {
+        let walls = self.door_segments(from, to, open);
+        self.walls.extend(walls);
+    }
+
+    // A wall along the grid line from `from` to `to`, with a gap in it if it has a door.
+    fn door_segments(&mut self, from: Vec2, to: Vec2, open: bool) -> Vec<Rect> {
+        let thickness = Vec2::splat(WALL_THICKNESS / 2.);
+        let segment = |a: Vec2, b: Vec2| Rect::from_corners(a - thickness, b + thickness);
+
+        let length = from.distance(to);
+        if !open || length < DOOR_WIDTH + WALL_THICKNESS * 4. {
+            return vec![segment(from, to)];
+        }
+
+        let direction = (to - from) / length;
+        let start = self
+            .rng
+            .gen_range(WALL_THICKNESS * 2.0..=length - DOOR_WIDTH - WALL_THICKNESS * 2.);
+        vec![
+            segment(from, from + direction * start),
+            segment(from + direction * (start + DOOR_WIDTH), to),
+        ]
+    }
+
+    fn room_cover(&mut self, room: Rect) {
+        let inner = room.inflate(-ROOM_MARGIN);
+        if inner.is_empty() {
+            return;
+        }
+        let per_room = self.params.cover_density * room.width() * room.height() / 1_000_000.;
+        let count = self.rng.gen_range(0.0..=per_room * 2.).round() as usize;
+        for _ in 0..count {
+            self.place_cover(inner, 100.0..=300.0);
+        }
+    }
+
+    fn scatter(&mut self) {
+        let region = self.region.inflate(-ROOM_MARGIN);
+        let min_spacing = region.width().min(region.height()) / 3.;
+        for _ in 0..self.spawns_to_place() {
+            let mut best = region.center();
+            let mut best_distance = 0.;
+            // Keep whichever candidate is furthest from the spawns already placed.
+            for _ in 0..50 {
+                let candidate = random_point(&mut self.rng, region);
+                let distance = self
+                    .spawn_points
+                    .iter()
+                    .map(|spawn| spawn.distance(candidate))
+                    .fold(f32::MAX, f32::min);
+                if distance > best_distance {
+                    best = candidate;
+                    best_distance = distance;
+                }
+                if distance >= min_spacing {
+                    break;
+                }
+            }
+            self.spawn_points.push(best);
+        }
+
+        let area = self.region.width() * self.region.height();
+        let count = (self.params.cover_density * area / 1_000_000.).round() as usize;
+        for _ in 0..count {
+            self.place_cover(region, 100.0..=600.0);
+        }
+    }
+
+    // Tries a few random spots for a block of cover that keeps clear of spawn points
+    // and other cover, so it can't close off narrow gaps.
+    fn place_cover(&mut self, area: Rect, size: std::ops::RangeInclusive<f32>) {
+        for _ in 0..10 {
+            let extent = Vec2::new(
+                self.rng.gen_range(size.clone()),
+                self.rng.gen_range(size.clone()),
+            );
+            let min = random_point(
+                &mut self.rng,
+                Rect::from_corners(area.min, area.max - extent),
+            );
+            let cover = Rect::from_corners(min, min + extent);
+            if !area.contains(cover.min) || !area.contains(cover.max) {
+                continue;
+            }
+
+            let blocked = self
+                .spawn_points
+                .iter()
+                .any(|&spawn| near_rect(cover, spawn, SPAWN_CLEARANCE))
+                || self
+                    .walls
+                    .iter()
+                    .any(|wall| !wall.intersect(cover.inflate(COVER_GAP)).is_empty());
+            if !blocked {
+                self.walls.push(cover);
+                return;
+            }
+        }
+    }
+
+    fn mirror(&mut self) {
+        if self.params.symmetric {
+            let mirrored: Vec<Rect> = self.walls.iter().map(|&wall| mirror_rect(wall)).collect();
+            self.walls.extend(mirrored);
+
+            // Interleaved, so players joining one after another start on opposite sides.
+            self.spawn_points = self
+                .spawn_points
+                .iter()
+                .flat_map(|&spawn| [spawn, Vec2::new(-spawn.x, spawn.y)])
+                .take(self.params.spawn_count)
+                .collect();
+        }
+        self.walls.append(&mut self.center_walls);
+    }
+
+    // The layouts should already be connected, but cover and doors can still line up
+    // badly. Any spawn the first one can't path to gets a corridor carved towards it.
+    fn connect_spawns(&mut self) {
+        let bounds = self.params.bounds;
+        for _ in 0..self.spawn_points.len() {
+            let nav_grid = NavGrid::build(
+                self.walls
+                    .iter()
+                    .map(|wall| Block::from_rect(wall.min, wall.max).points)
+                    .chain(bounds.boundary_walls().map(|block| block.points)),
+                bounds.min(),
+                bounds.max(),
+                NAV_CELL_SIZE,
+                NAV_AGENT_RADIUS,
+            );
+
+            let Some(&start) = self.spawn_points.first() else {
+                return;
+            };
+            let Some(&goal) = self
+                .spawn_points
+                .iter()
+                .skip(1)
+                .find(|&&goal| nav_grid.find_path(start, goal).is_none())
+            else {
+                return;
+            };
+
+            self.carve(start, goal);
+            if self.params.symmetric {
+                let mirror = |point: Vec2| Vec2::new(-point.x, point.y);
+                self.carve(mirror(start), mirror(goal));
+            }
+        }
+    }
+
+    fn carve(&mut self, from: Vec2, to: Vec2) {
+        let clearance = NAV_AGENT_RADIUS + NAV_CELL_SIZE;
+        self.walls.retain(|wall| {
+            let inflated = wall.inflate(clearance);
+            let points = Block::from_rect(inflated.min, inflated.max).points;
+            !segment_hits_polygon(from, to, &points)
+        });
+    }
+}
+
+fn random_point(rng: &mut StdRng, area: Rect) -> Vec2 {
+    Vec2::new(
+        rng.gen_range(area.min.x..=area.max.x),
+        rng.gen_range(area.min.y..=area.max.y),
+    )
+}
+
+fn near_rect(rect: Rect, point: Vec2, distance: f32) -> bool {
+    point.clamp(rect.min, rect.max).distance(point) < distance
+}
+
+fn mirror_rect(rect: Rect) -> Rect {
+    Rect::from_corners(
+        Vec2::new(-rect.max.x, rect.min.y),
+        Vec2::new(-rect.min.x, rect.max.y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(layout: Layout, symmetric: bool) -> GeneratorParams {
+        GeneratorParams {
+            layout,
+            symmetric,
+            ..Default::default()
+        }
+    }
+
+    fn all_params() -> Vec<GeneratorParams> {
+        [Layout::Rooms, Layout::Scatter]
+            .into_iter()
+            .flat_map(|layout| [true, false].map(|symmetric| params(layout, symmetric)))
+            .collect()
+    }
+
+    fn json(map: &MapData) -> String {
+        serde_json::to_string(map).unwrap()
+    }
+
+    #[test]
+    fn same_seed_gives_same_map() {
+        for params in all_params() {
+            assert_eq!(json(&generate(7, &params)), json(&generate(7, &params)));
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_maps() {
+        for params in all_params() {
+            assert_ne!(json(&generate(7, &params)), json(&generate(8, &params)));
+        }
+    }
+
+    #[test]
+    fn symmetric_maps_mirror_their_walls() {
+        let map = generate(3, &params(Layout::Rooms, true));
+        let rects: Vec<Rect> = map
+            .walls
+            .iter()
+            .map(|wall| wall.block().points)
+            .map(|points| {
+                points
+                    .iter()
+                    .fold(Rect::EMPTY, |rect, &point| rect.union_point(point))
+            })
+            .collect();
+        for rect in &rects {
+            let mirrored = mirror_rect(*rect);
+            assert!(
+                rects.iter().any(|other| {
+                    other.min.distance(mirrored.min) < 0.01
+                        && other.max.distance(mirrored.max) < 0.01
+                }),
+                "{:?} has no mirror image",
+                rect
+            );
+        }
+    }
+
+    #[test]
+    fn every_spawn_can_reach_every_other() {
+        for params in all_params() {
+            for seed in 0..4 {
+                let map = generate(seed, &params);
+                let nav_grid = NavGrid::build(
+                    map.blocks().map(|block| block.points),
+                    map.bounds.min(),
+                    map.bounds.max(),
+                    NAV_CELL_SIZE,
+                    NAV_AGENT_RADIUS,
+                );
+                let spawns: Vec<Vec2> = map
+                    .spawn_points
+                    .iter()
+                    .map(|&(x, y)| Vec2::new(x, y))
+                    .collect();
+                assert!(spawns.len() >= params.spawn_count);
+                for &goal in &spawns[1..] {
+                    assert!(
+                        nav_grid.find_path(spawns[0], goal).is_some(),
+                        "seed {} ({:?}, symmetric: {}): no path from {} to {}",
+                        seed,
+                        params.layout,
+                        params.symmetric,
+                        spawns[0],
+                        goal
+                    );
+                }
+            }
+        }
+    }
+}
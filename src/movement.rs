@@ -0,0 +1,579 @@
+use bevy::prelude::*;
+
+use crate::{
+    ai::AiController,
+    broadphase::{update_body_broadphase, BlockBroadphase},
+    colliders::colliders_o::{swept_circle_polygon, BlockCollider},
+    death::{player_alive, Dead},
+    editor::GameMode,
+    enemy::EnemyPlayer,
+    map::{MapBounds, MapState},
+    pickups::SPEED_BOOST,
+    player::{player_movement, Player, PLAYER_RADIUS},
+    weapons::WeaponKind,
+};
+
+// Units per second, and per second squared.
+const MAX_SPEED: f32 = 800.;
+const ACCELERATION: f32 = 6000.;
+const DECELERATION: f32 = 4500.;
+// Share of the usual speed left while stunned.
+pub const STUN_SLOW: f32 = 0.5;
+pub const MAX_STAMINA: f32 = 100.;
+pub const SPRINT_FACTOR: f32 = 1.6;
+// Stamina per second while sprinting.
+const SPRINT_COST: f32 = 25.;
+const DASH_COST: f32 = 30.;
+const DASH_SPEED: f32 = 3200.;
+const DASH_SECS: f32 = 0.15;
+const DASH_COOLDOWN: f32 = 0.8;
+// Stamina per second coming back, once it's been left alone for `REGEN_DELAY`.
+const REGEN: f32 = 20.;
+const REGEN_DELAY: f32 = 0.6;
+// Seconds between afterimages while dashing and while sprinting.
+const DASH_TRAIL: f32 = 0.025;
+const SPRINT_TRAIL: f32 = 0.12;
+const AFTERIMAGE_SECONDS: f32 = 0.25;
+
+// How a player body gets going, slows down and tops out. The same steps run for the
+// local player, for guessing where remote players have got to between updates, and in
+// the bots.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Momentum {
+    pub velocity: Vec2,
+    // The direction the body is being steered in, zero if it's left to slow down.
+    pub heading: Vec2,
+    pub acceleration: f32,
+    pub deceleration: f32,
+    pub max_speed: f32,
+}
+
+impl Default for Momentum {
+    fn default() -> Self {
+        Momentum {
+            velocity: Vec2::ZERO,
+            heading: Vec2::ZERO,
+            acceleration: ACCELERATION,
+            deceleration: DECELERATION,
+            max_speed: MAX_SPEED,
+        }
+    }
+}
+
+impl Momentum {
+    // Steers toward `heading` at `speed_factor` times the usual top speed for `dt`, and
+    // returns how far the body moves.
+    pub fn step(&mut self, heading: Vec2, speed_factor: f32, dt: f32) -> Vec2 {
+        self.heading = heading.normalize_or_zero();
+        let target = self.heading * self.max_speed * speed_factor;
+        // Anything over the target, like being thrown, bleeds off as if let go.
+        let rate = if self.heading == Vec2::ZERO || self.velocity.length() > target.length() {
+            self.deceleration
+        } else {
+            self.acceleration
+        };
+        self.velocity += (target - self.velocity).clamp_length_max(rate * dt);
+        self.velocity * dt
+    }
+
+    // Throws the body along `direction` hard enough to slide about `distance` before
+    // friction stops it.
+    pub fn shove(&mut self, direction: Vec2, distance: f32) {
+        self.velocity += direction.normalize_or_zero() * self.shove_speed(distance);
+    }
+
+    pub fn shove_speed(&self, distance: f32) -> f32 {
+        (2. * self.deceleration * distance.max(0.)).sqrt()
+    }
+
+    // Drops whatever part of the velocity goes into a surface facing `normal`, so the
+    // body slides along walls instead of pressing into them.
+    pub fn stop_against(&mut self, normal: Vec2) {
+        let into = self.velocity.dot(normal);
+        if into < 0. {
+            self.velocity -= normal * into;
+        }
+    }
+
+    // The fastest a body can go under its own steam, with every boost running.
+    pub fn top_speed(&self) -> f32 {
+        self.max_speed * SPRINT_FACTOR * SPEED_BOOST
+    }
+
+    // The fastest a body can be going at all: top speed, plus being thrown by the
+    // hardest-hitting shot or blast there is.
+    pub fn speed_limit(&self) -> f32 {
+        let hardest_hit = WeaponKind::ALL
+            .iter()
+            .map(|kind| {
+                let stats = kind.stats();
+                let shot = stats.projectiles as f32 * self.shove_speed(stats.knockback);
+                let blast = stats
+                    .explosive
+                    .map_or(0., |explosive| self.shove_speed(explosive.knockback));
+                shot.max(blast)
+            })
+            .fold(0., f32::max);
+        self.top_speed() + hardest_hit
+    }
+}
+
+// Slowed down for a moment after taking a hard hit.
+#[derive(Component, Debug)]
+pub struct HitStun(Timer);
+
+impl HitStun {
+    pub fn new(secs: f32) -> Self {
+        HitStun(Timer::from_seconds(secs, TimerMode::Once))
+    }
+}
+
+// The local player's stamina, which sprinting and dashing run on.
+#[derive(Component, Debug)]
+pub struct Stamina {
+    pub current: f32,
+    pub max: f32,
+    // Time until it starts coming back.
+    regen_delay: f32,
+    dash_cooldown: f32,
+    // Direction and time left of the dash in progress.
+    dash: Option<(Vec2, f32)>,
+    pub sprinting: bool,
+}
+
+impl Stamina {
+    pub fn new(max: f32) -> Self {
+        Stamina {
+            current: max,
+            max,
+            regen_delay: 0.,
+            dash_cooldown: 0.,
+            dash: None,
+            sprinting: false,
+        }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        (self.current / self.max.max(1.)).clamp(0., 1.)
+    }
+
+    pub fn dashing(&self) -> bool {
+        self.dash.is_some()
+    }
+
+    fn spend(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.);
+        self.regen_delay = REGEN_DELAY;
+    }
+
+    // Sprints for `dt` if the player wants to and there's stamina left. Returns whether
+    // they're sprinting.
+    pub fn sprint(&mut self, wants: bool, dt: f32) -> bool {
+        self.sprinting = wants && self.current > 0.;
+        if self.sprinting {
+            self.spend(SPRINT_COST * dt);
+        }
+        self.sprinting
+    }
+
+    fn try_dash(&mut self, direction: Vec2) -> bool {
+        if self.dashing() || self.dash_cooldown > 0. || self.current < DASH_COST {
+            return false;
+        }
+        self.spend(DASH_COST);
+        self.dash_cooldown = DASH_COOLDOWN;
+        self.dash = Some((direction, DASH_SECS));
+        true
+    }
+
+    fn recover(&mut self, dt: f32) {
+        self.dash_cooldown = (self.dash_cooldown - dt).max(0.);
+        if self.regen_delay > 0. {
+            self.regen_delay -= dt;
+        } else {
+            self.current = (self.current + REGEN * dt).min(self.max);
+        }
+    }
+}
+
+// What a player's legs are doing, for the trail left behind them. The local player's
+// comes from its `Stamina`, remote players' from the network.
+#[derive(Component, Default, Debug)]
+pub struct MovementState {
+    pub sprinting: bool,
+    pub dashing: bool,
+    // Time until the next afterimage.
+    trail: f32,
+}
+
+#[derive(Component)]
+pub struct Afterimage {
+    lifetime: Timer,
+    alpha: f32,
+}
+
+#[derive(Component)]
+pub struct StaminaBar;
+
+pub struct MovementPlugin;
+
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(MapState::Ready), spawn_stamina_bar)
+            .add_systems(
+                Update,
+                (
+                    (start_dash, apply_dash)
+                        .chain()
+                        .after(player_movement)
+                        .before(update_body_broadphase)
+                        .run_if(player_alive),
+                    coast_remote_players.before(update_body_broadphase),
+                    recover_stamina,
+                    wear_off_hit_stun,
+                    sync_movement_state,
+                    leave_afterimages,
+                    fade_afterimages,
+                    update_stamina_bar,
+                )
+                    .run_if(in_state(MapState::Ready).and(in_state(GameMode::Play))),
+            );
+    }
+}
+
+// The WASD direction, normalized, or zero.
+pub fn input_direction(keyboard: &ButtonInput<KeyCode>) -> Vec2 {
+    let mut direction = Vec2::ZERO;
+    if keyboard.pressed(KeyCode::KeyW) {
+        direction.y += 1.;
+    }
+    if keyboard.pressed(KeyCode::KeyS) {
+        direction.y -= 1.;
+    }
+    if keyboard.pressed(KeyCode::KeyA) {
+        direction.x -= 1.;
+    }
+    if keyboard.pressed(KeyCode::KeyD) {
+        direction.x += 1.;
+    }
+    direction.normalize_or_zero()
+}
+
+pub fn start_dash(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut player_q: Query<&mut Stamina, With<Player>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Space) {
+        return;
+    }
+    let direction = input_direction(&keyboard);
+    if direction == Vec2::ZERO {
+        return;
+    }
+    for mut stamina in player_q.iter_mut() {
+        stamina.try_dash(direction);
+    }
+}
+
+// Dashes are fast enough to skip through a wall in one frame, so each step is swept
+// against the blocks and the dash ends at the first one it reaches.
+pub fn apply_dash(
+    mut player_q: Query<(&mut Transform, &mut Stamina), With<Player>>,
+    block_q: Query<&BlockCollider>,
+    broadphase: Res<BlockBroadphase>,
+    bounds: Res<MapBounds>,
+    time: Res<Time>,
+) {
+    let mut candidates = Vec::new();
+    for (mut transform, mut stamina) in player_q.iter_mut() {
+        let Some((direction, remaining)) = stamina.dash else {
+            continue;
+        };
+        let dt = time.delta_secs().min(remaining);
+        let from = transform.translation.truncate();
+        let to = from + direction * DASH_SPEED * dt;
+
+        let padding = Vec2::splat(PLAYER_RADIUS);
+        broadphase.query(
+            from.min(to) - padding,
+            from.max(to) + padding,
+            &mut candidates,
+        );
+        let hit = candidates
+            .iter()
+            .filter_map(|&block| block_q.get(block).ok())
+            .filter_map(|block| swept_circle_polygon(from, to, PLAYER_RADIUS, &block.points))
+            .map(|(t, _)| t)
+            .reduce(f32::min);
+
+        let limit = bounds.playable_half_extents() - padding;
+        let end = from.lerp(to, hit.unwrap_or(1.)).clamp(-limit, limit);
+        transform.translation.x = end.x;
+        transform.translation.y = end.y;
+
+        stamina.dash = if hit.is_some() || remaining <= dt {
+            None
+        } else {
+            Some((direction, remaining - dt))
+        };
+    }
+}
+
+// Other people's players, as opposed to the AI's.
+type RemotePlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Transform,
+        &'static mut Momentum,
+        &'static MovementState,
+    ),
+    (With<EnemyPlayer>, Without<AiController>),
+>;
+
+// Remote players keep going the way they last said they were between updates.
+pub fn coast_remote_players(
+    mut enemy_q: RemotePlayerQuery,
+    bounds: Res<MapBounds>,
+    time: Res<Time>,
+) {
+    let limit = bounds.playable_half_extents() - Vec2::splat(PLAYER_RADIUS);
+    for (mut transform, mut momentum, state) in enemy_q.iter_mut() {
+        let factor = if state.sprinting { SPRINT_FACTOR } else { 1. };
+        let heading = momentum.heading;
+        let step = momentum.step(heading, factor, time.delta_secs());
+        let position = (transform.translation.truncate() + step).clamp(-limit, limit);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+pub fn wear_off_hit_stun(
+    mut commands: Commands,
+    mut stun_q: Query<(Entity, &mut HitStun)>,
+    time: Res<Time>,
+) {
+    for (entity, mut stun) in stun_q.iter_mut() {
+        if stun.0.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<HitStun>();
+        }
+    }
+}
+
+pub fn recover_stamina(mut stamina_q: Query<&mut Stamina>, time: Res<Time>) {
+    for mut stamina in stamina_q.iter_mut() {
+        stamina.recover(time.delta_secs());
+    }
+}
+
+pub fn sync_movement_state(mut player_q: Query<(&Stamina, &mut MovementState)>) {
+    for (stamina, mut state) in player_q.iter_mut() {
+        state.sprinting = stamina.sprinting;
+        state.dashing = stamina.dashing();
+    }
+}
+
+// Fading copies of the body left behind while dashing, and more sparsely while
+// sprinting, so everyone can see who's on the move.
+pub fn leave_afterimages(
+    mut commands: Commands,
+    mut mover_q: Query<
+        (
+            &Transform,
+            &mut MovementState,
+            &Mesh2d,
+            &MeshMaterial2d<ColorMaterial>,
+        ),
+        Without<Dead>,
+    >,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time>,
+) {
+    for (transform, mut state, mesh, material) in mover_q.iter_mut() {
+        let interval = if state.dashing {
+            DASH_TRAIL
+        } else if state.sprinting {
+            SPRINT_TRAIL
+        } else {
+            state.trail = 0.;
+            continue;
+        };
+        state.trail -= time.delta_secs();
+        if state.trail > 0. {
+            continue;
+        }
+        state.trail = interval;
+
+        let Some(color) = materials.get(&material.0).map(|material| material.color) else {
+            continue;
+        };
+        let alpha = if state.dashing { 0.5 } else { 0.25 };
+        commands.spawn((
+            Afterimage {
+                lifetime: Timer::from_seconds(AFTERIMAGE_SECONDS, TimerMode::Once),
+                alpha,
+            },
+            mesh.clone(),
+            MeshMaterial2d(materials.add(color.with_alpha(alpha))),
+            Transform::from_translation(transform.translation.with_z(1.0)),
+        ));
+    }
+}
+
+pub fn fade_afterimages(
+    mut commands: Commands,
+    mut afterimage_q: Query<(
+        Entity,
+        &mut Afterimage,
+        &mut Transform,
+        &MeshMaterial2d<ColorMaterial>,
+    )>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time>,
+) {
+    for (entity, mut afterimage, mut transform, material) in afterimage_q.iter_mut() {
+        afterimage.lifetime.tick(time.delta());
+        if afterimage.lifetime.finished() {
+            materials.remove(&material.0);
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let remaining = afterimage.lifetime.fraction_remaining();
+        transform.scale = Vec3::splat(0.7 + 0.3 * remaining);
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color.set_alpha(afterimage.alpha * remaining);
+        }
+    }
+}
+
+fn spawn_stamina_bar(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(20.),
+                left: Val::Percent(50.),
+                width: Val::Px(300.),
+                height: Val::Px(14.),
+                margin: UiRect::left(Val::Px(-150.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.5)),
+        ))
+        .with_children(|bar| {
+            bar.spawn((
+                StaminaBar,
+                Node {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.95, 0.8, 0.2)),
+            ));
+        });
+}
+
+fn update_stamina_bar(
+    stamina_q: Query<&Stamina, With<Player>>,
+    mut bar_q: Query<&mut Node, With<StaminaBar>>,
+) {
+    let Ok(stamina) = stamina_q.get_single() else {
+        return;
+    };
+    for mut node in bar_q.iter_mut() {
+        node.width = Val::Percent(stamina.fraction() * 100.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1. / 1000.;
+
+    // Steps for `secs` and returns how far the body went.
+    fn run(momentum: &mut Momentum, heading: Vec2, secs: f32) -> Vec2 {
+        let steps = (secs / DT).round() as usize;
+        (0..steps).map(|_| momentum.step(heading, 1., DT)).sum()
+    }
+
+    #[test]
+    fn speeds_up_to_max_speed_and_no_further() {
+        let mut momentum = Momentum::default();
+        let ramp = momentum.max_speed / momentum.acceleration;
+
+        run(&mut momentum, Vec2::X, ramp / 2.);
+        // Within the one step `run` may round to.
+        let one_step = momentum.acceleration * DT;
+        assert!((momentum.velocity.x - momentum.max_speed / 2.).abs() <= one_step);
+
+        run(&mut momentum, Vec2::X, ramp);
+        assert!((momentum.velocity.x - momentum.max_speed).abs() < 1e-3);
+        assert_eq!(momentum.velocity.y, 0.);
+    }
+
+    #[test]
+    fn letting_go_slows_to_a_stop_without_reversing() {
+        let mut momentum = Momentum {
+            velocity: Vec2::new(MAX_SPEED, 0.),
+            ..default()
+        };
+        let stop = momentum.max_speed / momentum.deceleration;
+
+        run(&mut momentum, Vec2::ZERO, stop / 2.);
+        assert!(momentum.velocity.x > 0.);
+        run(&mut momentum, Vec2::ZERO, stop);
+        assert_eq!(momentum.velocity, Vec2::ZERO);
+    }
+
+    #[test]
+    fn turning_around_takes_time() {
+        let mut momentum = Momentum {
+            velocity: Vec2::new(MAX_SPEED, 0.),
+            ..default()
+        };
+        momentum.step(Vec2::NEG_X, 1., 0.05);
+        assert!(momentum.velocity.x > 0.);
+    }
+
+    #[test]
+    fn shoves_slide_about_the_asked_distance() {
+        let mut momentum = Momentum::default();
+        momentum.shove(Vec2::Y, 300.);
+        let travelled = run(&mut momentum, Vec2::ZERO, 2.);
+        assert!((travelled.y - 300.).abs() < 5., "{}", travelled);
+        assert_eq!(momentum.velocity, Vec2::ZERO);
+    }
+
+    #[test]
+    fn being_thrown_bleeds_off_even_while_steering() {
+        let mut momentum = Momentum::default();
+        momentum.shove(Vec2::X, 500.);
+        let thrown = momentum.velocity.x;
+        momentum.step(Vec2::X, 1., 0.1);
+        let expected = thrown - momentum.deceleration * 0.1;
+        assert!((momentum.velocity.x - expected).abs() < 1e-2);
+    }
+
+    #[test]
+    fn walls_stop_only_the_part_going_into_them() {
+        let mut momentum = Momentum {
+            velocity: Vec2::new(-300., 200.),
+            ..default()
+        };
+        momentum.stop_against(Vec2::X);
+        assert_eq!(momentum.velocity, Vec2::new(0., 200.));
+
+        // Moving away from a wall isn't touched.
+        momentum.velocity = Vec2::new(300., 200.);
+        momentum.stop_against(Vec2::X);
+        assert_eq!(momentum.velocity, Vec2::new(300., 200.));
+    }
+
+    #[test]
+    fn speed_limit_leaves_room_for_knockback() {
+        let momentum = Momentum::default();
+        assert!(momentum.top_speed() >= momentum.max_speed);
+        assert!(momentum.speed_limit() > momentum.top_speed());
+    }
+}
@@ -0,0 +1,407 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use bevy::prelude::*;
+
+use crate::{
+    colliders::colliders_o::{circle_polygon_push, BlockCollider},
+    map::MapBounds,
+};
+
+pub const NAV_CELL_SIZE: f32 = 50.0;
+pub const NAV_AGENT_RADIUS: f32 = 70.0;
+
+#[derive(Resource, Clone)]
+pub struct NavGrid {
+    origin: Vec2,
+    cell_size: f32,
+    width: usize,
+    height: usize,
+    blocked: Vec<bool>,
+}
+
+impl NavGrid {
+    // Marks every cell whose center lies within `radius` of a block as blocked, so a
+    // circle of that radius can stand at the center of any free cell.
+    pub fn build<P: AsRef<[Vec2]>>(
+        blocks: impl IntoIterator<Item = P>,
+        min: Vec2,
+        max: Vec2,
+        cell_size: f32,
+        radius: f32,
+    ) -> Self {
+        let width = ((max.x - min.x) / cell_size).ceil() as usize;
+        let height = ((max.y - min.y) / cell_size).ceil() as usize;
+        let mut grid = NavGrid {
+            origin: min,
+            cell_size,
+            width,
+            height,
+            blocked: vec![false; width * height],
+        };
+
+        for points in blocks {
+            let points = points.as_ref();
+            let block_min = points.iter().copied().fold(Vec2::MAX, Vec2::min);
+            let block_max = points.iter().copied().fold(Vec2::MIN, Vec2::max);
+
+            let (Some((x0, y0)), Some((x1, y1))) = (
+                grid.cell_of(block_min - Vec2::splat(radius)),
+                grid.cell_of(block_max + Vec2::splat(radius)),
+            ) else {
+                break;
+            };
+
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let center = grid.cell_center(x, y);
+                    if circle_polygon_push(center, radius, points).is_some() {
+                        grid.blocked[y * width + x] = true;
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    pub fn from_blocks<'a>(
+        blocks: impl IntoIterator<Item = &'a BlockCollider>,
+        bounds: &MapBounds,
+    ) -> Self {
+        Self::build(
+            blocks.into_iter().map(|block| &block.points),
+            bounds.min(),
+            bounds.max(),
+            NAV_CELL_SIZE,
+            NAV_AGENT_RADIUS,
+        )
+    }
+
+    // The cell under `position`, or the nearest one along the edge if it's outside the
+    // grid. Only an empty grid has none.
+    fn cell_of(&self, position: Vec2) -> Option<(usize, usize)> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let local = (position - self.origin) / self.cell_size;
+        Some((
+            (local.x.max(0.) as usize).min(self.width - 1),
+            (local.y.max(0.) as usize).min(self.height - 1),
+        ))
+    }
+
+    fn cell_center(&self, x: usize, y: usize) -> Vec2 {
+        self.origin + (Vec2::new(x as f32, y as f32) + 0.5) * self.cell_size
+    }
+
+    fn is_blocked(&self, x: usize, y: usize) -> bool {
+        self.blocked[y * self.width + x]
+    }
+
+    pub fn is_walkable(&self, position: Vec2) -> bool {
+        self.cell_of(position)
+            .is_some_and(|(x, y)| !self.is_blocked(x, y))
+    }
+
+    // Closest free cell to `position`, searched in growing rings.
+    fn nearest_free(&self, position: Vec2) -> Option<(usize, usize)> {
+        let (cx, cy) = self.cell_of(position)?;
+        if !self.is_blocked(cx, cy) {
+            return Some((cx, cy));
+        }
+
+        let max_ring = self.width.max(self.height) as isize;
+        for ring in 1..max_ring {
+            let mut best: Option<((usize, usize), f32)> = None;
+            for dy in -ring..=ring {
+                for dx in -ring..=ring {
+                    if dx.abs() != ring && dy.abs() != ring {
+                        continue;
+                    }
+                    let (x, y) = (cx as isize + dx, cy as isize + dy);
+                    if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+                        continue;
+                    }
+                    let (x, y) = (x as usize, y as usize);
+                    if self.is_blocked(x, y) {
+                        continue;
+                    }
+                    let distance = self.cell_center(x, y).distance_squared(position);
+                    if best.is_none_or(|(_, d)| distance < d) {
+                        best = Some(((x, y), distance));
+                    }
+                }
+            }
+            if let Some((cell, _)) = best {
+                return Some(cell);
+            }
+        }
+
+        None
+    }
+
+    // Walks the cells under the segment and reports whether all of them are free.
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let distance = from.distance(to);
+        let steps = (distance / (self.cell_size * 0.5)).ceil().max(1.) as usize;
+        (0..=steps).all(|i| self.is_walkable(from.lerp(to, i as f32 / steps as f32)))
+    }
+
+    // A* over the 8-connected grid. Returns the waypoints to walk from `start` to
+    // `goal`, not including `start`, with redundant corners removed.
+    pub fn find_path(&self, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+        let start_cell = self.nearest_free(start)?;
+        let goal_cell = self.nearest_free(goal)?;
+        let index = |(x, y): (usize, usize)| y * self.width + x;
+
+        let heuristic = |(x, y): (usize, usize)| {
+            let dx = (x as f32 - goal_cell.0 as f32).abs();
+            let dy = (y as f32 - goal_cell.1 as f32).abs();
+            dx.max(dy) + (std::f32::consts::SQRT_2 - 1.) * dx.min(dy)
+        };
+
+        let mut cost = vec![f32::INFINITY; self.width * self.height];
+        let mut came_from = vec![usize::MAX; self.width * self.height];
+        let mut open = BinaryHeap::new();
+
+        cost[index(start_cell)] = 0.;
+        open.push(OpenCell {
+            estimate: heuristic(start_cell),
+            cell: start_cell,
+        });
+
+        while let Some(OpenCell { cell, .. }) = open.pop() {
+            if cell == goal_cell {
+                break;
+            }
+            let current_cost = cost[index(cell)];
+
+            for (dx, dy) in [
+                (-1, 0),
+                (1, 0),
+                (0, -1),
+                (0, 1),
+                (-1, -1),
+                (1, -1),
+                (-1, 1),
+                (1, 1),
+            ] {
+                let (nx, ny) = (cell.0 as isize + dx, cell.1 as isize + dy);
+                if nx < 0 || ny < 0 || nx >= self.width as isize || ny >= self.height as isize {
+                    continue;
+                }
+                let next = (nx as usize, ny as usize);
+                if self.is_blocked(next.0, next.1) {
+                    continue;
+                }
+                // Don't cut corners diagonally past a blocked cell.
+                if dx != 0
+                    && dy != 0
+                    && (self.is_blocked(next.0, cell.1) || self.is_blocked(cell.0, next.1))
+                {
+                    continue;
+                }
+
+                let step = if dx != 0 && dy != 0 {
+                    std::f32::consts::SQRT_2
+                } else {
+                    1.
+                };
+                let next_cost = current_cost + step;
+                if next_cost < cost[index(next)] {
+                    cost[index(next)] = next_cost;
+                    came_from[index(next)] = index(cell);
+                    open.push(OpenCell {
+                        estimate: next_cost + heuristic(next),
+                        cell: next,
+                    });
+                }
+            }
+        }
+
+        if cost[index(goal_cell)].is_infinite() {
+            return None;
+        }
+
+        let mut cells = vec![goal_cell];
+        let mut current = index(goal_cell);
+        while current != index(start_cell) {
+            current = came_from[current];
+            cells.push((current % self.width, current / self.width));
+        }
+        cells.reverse();
+
+        let mut points: Vec<Vec2> = cells
+            .into_iter()
+            .map(|(x, y)| self.cell_center(x, y))
+            .collect();
+        if self.is_walkable(goal) {
+            *points.last_mut().unwrap() = goal;
+        }
+
+        let mut waypoints = Vec::new();
+        let mut anchor = start;
+        let mut i = 0;
+        while i < points.len() {
+            let mut furthest = i;
+            while furthest + 1 < points.len() && self.line_of_sight(anchor, points[furthest + 1]) {
+                furthest += 1;
+            }
+            anchor = points[furthest];
+            waypoints.push(anchor);
+            i = furthest + 1;
+        }
+
+        Some(waypoints)
+    }
+}
+
+#[derive(PartialEq)]
+struct OpenCell {
+    estimate: f32,
+    cell: (usize, usize),
+}
+
+impl Eq for OpenCell {}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so the BinaryHeap pops the lowest estimate first.
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(NavGrid::from_blocks(
+            std::iter::empty(),
+            &MapBounds::default(),
+        ))
+        .add_systems(PreUpdate, rebuild_nav_grid);
+    }
+}
+
+pub fn rebuild_nav_grid(
+    mut nav_grid: ResMut<NavGrid>,
+    changed: Query<(), Changed<BlockCollider>>,
+    mut removed: RemovedComponents<BlockCollider>,
+    blocks: Query<&BlockCollider>,
+    bounds: Res<MapBounds>,
+) {
+    let removed_any = removed.read().count() > 0;
+    if changed.is_empty() && !removed_any && !bounds.is_changed() {
+        return;
+    }
+
+    *nav_grid = NavGrid::from_blocks(blocks.iter(), &bounds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::Block, colliders::colliders_o::segment_hits_polygon};
+
+    const HALF: f32 = 1000.;
+    const RADIUS: f32 = 20.;
+
+    fn grid(blocks: &[Vec<Vec2>]) -> NavGrid {
+        NavGrid::build(
+            blocks,
+            Vec2::splat(-HALF),
+            Vec2::splat(HALF),
+            NAV_CELL_SIZE,
+            RADIUS,
+        )
+    }
+
+    fn rect(min: (f32, f32), max: (f32, f32)) -> Vec<Vec2> {
+        Block::from_rect(min.into(), max.into()).points
+    }
+
+    // A vertical wall down the middle with a gap at the top.
+    fn wall_with_gap() -> Vec<Vec<Vec2>> {
+        vec![rect((-50., -HALF), (50., 600.))]
+    }
+
+    #[test]
+    fn open_ground_goes_straight_to_the_goal() {
+        let goal = Vec2::new(700., 300.);
+        let path = grid(&[]).find_path(Vec2::new(-700., -300.), goal).unwrap();
+        assert_eq!(path, vec![goal]);
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let blocks = wall_with_gap();
+        let start = Vec2::new(-500., -500.);
+        let goal = Vec2::new(500., -500.);
+        let path = grid(&blocks).find_path(start, goal).unwrap();
+
+        assert_eq!(path.last(), Some(&goal));
+        let mut from = start;
+        for &to in &path {
+            assert!(
+                !segment_hits_polygon(from, to, &blocks[0]),
+                "{} -> {} crosses the wall",
+                from,
+                to
+            );
+            from = to;
+        }
+        // The only way through is over the top of the wall.
+        assert!(path.iter().any(|point| point.y > 600.));
+    }
+
+    #[test]
+    fn no_path_into_a_sealed_room() {
+        let blocks = vec![
+            rect((200., 200.), (600., 250.)),
+            rect((200., 550.), (600., 600.)),
+            rect((200., 200.), (250., 600.)),
+            rect((550., 200.), (600., 600.)),
+        ];
+        let path = grid(&blocks).find_path(Vec2::new(-500., -500.), Vec2::new(400., 400.));
+        assert_eq!(path, None);
+    }
+
+    #[test]
+    fn starting_inside_a_wall_steps_out_first() {
+        let blocks = wall_with_gap();
+        let navigation = grid(&blocks);
+        let start = Vec2::new(0., 0.);
+        assert!(!navigation.is_walkable(start));
+
+        let goal = Vec2::new(500., 0.);
+        let path = navigation.find_path(start, goal).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+    }
+
+    #[test]
+    fn line_of_sight_is_cut_by_walls() {
+        let navigation = grid(&wall_with_gap());
+        assert!(!navigation.line_of_sight(Vec2::new(-500., 0.), Vec2::new(500., 0.)));
+        assert!(navigation.line_of_sight(Vec2::new(-500., 800.), Vec2::new(500., 800.)));
+    }
+
+    #[test]
+    fn empty_grid_has_no_paths() {
+        let navigation = NavGrid::build(
+            wall_with_gap(),
+            Vec2::ZERO,
+            Vec2::ZERO,
+            NAV_CELL_SIZE,
+            RADIUS,
+        );
+        assert!(!navigation.is_walkable(Vec2::ZERO));
+        assert_eq!(navigation.find_path(Vec2::ZERO, Vec2::new(100., 0.)), None);
+    }
+}
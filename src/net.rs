@@ -0,0 +1,218 @@
+use std::io::Read;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{map::MapSource, player::Player, weapons::Loadout};
+
+// The first thing a client hears after sending its id: the map the server is running,
+// and where the server put its player.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Welcome {
+    pub map: MapSource,
+    pub spawn: (f32, f32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ClientMessage {
+    Update(Player),
+    // One of this client's bullets hit a destructible block.
+    DamageBlock { index: usize, damage: i32 },
+    // This client's player is dead and has waited out its respawn timer.
+    Respawn,
+    // What this client's player spawns with from now on.
+    Loadout(Loadout),
+    // This client's player is standing on the pickup at `index`.
+    ClaimPickup { index: usize },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    Update(Player),
+    // Authoritative health of the map wall at `index`. Zero or less means destroyed.
+    BlockHealth {
+        index: usize,
+        health: i32,
+    },
+    // Sent back to a client whose player moved into someone else, with where the server
+    // put it instead.
+    Correction {
+        position: (f32, f32),
+    },
+    // Where a client's dead player comes back, at full health.
+    Respawn {
+        position: (f32, f32),
+    },
+    // The pickup at `index` went to the player `by` (empty if it's from before the client
+    // joined), and comes back in `respawn_in` seconds.
+    PickupTaken {
+        index: usize,
+        by: String,
+        respawn_in: f32,
+    },
+}
+
+// Nothing sent either way comes close; anything claiming to be bigger is a broken or
+// hostile stream, not a message worth allocating for.
+pub const MAX_MESSAGE: usize = 64 * 1024;
+// Each message goes out as its length, then its bytes.
+const LENGTH_PREFIX: usize = 4;
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_MESSAGE as u64)
+}
+
+pub fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    let body = options()
+        .serialize(message)
+        .expect("Failed to serialize message");
+    let mut framed = Vec::with_capacity(LENGTH_PREFIX + body.len());
+    framed.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    framed.extend_from_slice(&body);
+    framed
+}
+
+// Reads exactly one message from a blocking stream, for the handshake before the
+// connection goes non-blocking.
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> Result<T, String> {
+    let mut prefix = [0; LENGTH_PREFIX];
+    reader.read_exact(&mut prefix).map_err(|e| e.to_string())?;
+    let len = u32::from_le_bytes(prefix) as usize;
+    if len > MAX_MESSAGE {
+        return Err(format!("message of {} bytes is over the limit", len));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    options().deserialize(&body).map_err(|e| e.to_string())
+}
+
+// A single read can end partway through a message or hold several, so bytes are kept
+// here until a whole one has arrived.
+#[derive(Default, Debug)]
+pub struct MessageBuffer {
+    pending: Vec<u8>,
+    // Number of times unparseable bytes had to be thrown away.
+    pub dropped: u64,
+}
+
+impl MessageBuffer {
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    pub fn drain<T: DeserializeOwned>(&mut self) -> Vec<T> {
+        let mut messages = Vec::new();
+        let mut consumed = 0;
+
+        while let Some(prefix) = self.pending.get(consumed..consumed + LENGTH_PREFIX) {
+            let len = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+            if len > MAX_MESSAGE {
+                // No telling where the next message starts; start over with whatever
+                // comes next.
+                self.dropped += 1;
+                consumed = self.pending.len();
+                break;
+            }
+            let start = consumed + LENGTH_PREFIX;
+            let Some(body) = self.pending.get(start..start + len) else {
+                // Incomplete; wait for the rest.
+                break;
+            };
+            match options().deserialize(body) {
+                Ok(message) => messages.push(message),
+                // The length still says where the next one starts, so only this one is lost.
+                Err(_) => self.dropped += 1,
+            }
+            consumed = start + len;
+        }
+
+        self.pending.drain(..consumed);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_health(index: usize) -> ServerMessage {
+        ServerMessage::BlockHealth { index, health: 500 }
+    }
+
+    fn indices(messages: &[ServerMessage]) -> Vec<usize> {
+        messages
+            .iter()
+            .map(|message| match message {
+                ServerMessage::BlockHealth { index, .. } => *index,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn several_messages_in_one_read_all_come_out_in_order() {
+        let mut buffer = MessageBuffer::default();
+        for index in 0..3 {
+            buffer.extend(&encode(&block_health(index)));
+        }
+        assert_eq!(indices(&buffer.drain()), vec![0, 1, 2]);
+        assert!(buffer.drain::<ServerMessage>().is_empty());
+        assert_eq!(buffer.dropped, 0);
+    }
+
+    #[test]
+    fn messages_split_across_reads_wait_for_the_rest() {
+        let mut buffer = MessageBuffer::default();
+        let bytes = encode(&block_health(7));
+        for byte in &bytes[..bytes.len() - 1] {
+            buffer.extend(&[*byte]);
+            assert!(buffer.drain::<ServerMessage>().is_empty());
+        }
+        buffer.extend(&bytes[bytes.len() - 1..]);
+        assert_eq!(indices(&buffer.drain()), vec![7]);
+    }
+
+    #[test]
+    fn oversized_lengths_throw_away_what_was_buffered() {
+        let mut buffer = MessageBuffer::default();
+        buffer.extend(&(MAX_MESSAGE as u32 + 1).to_le_bytes());
+        buffer.extend(&encode(&block_health(1)));
+        assert!(buffer.drain::<ServerMessage>().is_empty());
+        assert_eq!(buffer.dropped, 1);
+
+        // Whatever arrives after that is read as normal.
+        buffer.extend(&encode(&block_health(2)));
+        assert_eq!(indices(&buffer.drain()), vec![2]);
+    }
+
+    #[test]
+    fn an_unreadable_message_only_loses_itself() {
+        let mut buffer = MessageBuffer::default();
+        let mut bad = encode(&block_health(1));
+        // An enum variant that doesn't exist.
+        bad[LENGTH_PREFIX..LENGTH_PREFIX + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        buffer.extend(&bad);
+        buffer.extend(&encode(&block_health(2)));
+        assert_eq!(indices(&buffer.drain()), vec![2]);
+        assert_eq!(buffer.dropped, 1);
+    }
+
+    #[test]
+    fn read_message_reads_one_framed_message() {
+        let mut bytes = encode(&block_health(3));
+        bytes.extend(encode(&block_health(4)));
+        let mut reader = bytes.as_slice();
+        let message: ServerMessage = read_message(&mut reader).unwrap();
+        assert_eq!(indices(&[message]), vec![3]);
+        let message: ServerMessage = read_message(&mut reader).unwrap();
+        assert_eq!(indices(&[message]), vec![4]);
+    }
+
+    #[test]
+    fn read_message_refuses_oversized_lengths() {
+        let bytes = (MAX_MESSAGE as u32 + 1).to_le_bytes();
+        assert!(read_message::<ServerMessage>(&mut bytes.as_slice()).is_err());
+    }
+}
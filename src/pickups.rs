@@ -0,0 +1,313 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    death::{player_alive, Respawned},
+    editor::GameMode,
+    health::Health,
+    map::{MapState, PickupData, PickupKind},
+    player::{Player, PLAYER_RADIUS},
+    weapons::{Holstered, Weapon, WeaponKind, WeaponStats},
+};
+
+pub const PICKUP_RADIUS: f32 = 40.;
+const HEALTH_PACK: i32 = 400;
+const POWER_UP_SECS: f32 = 10.;
+pub const SPEED_BOOST: f32 = 1.5;
+const DAMAGE_BOOST: f32 = 1.5;
+// Share of incoming damage that gets through a shield.
+const SHIELD_FACTOR: f32 = 0.5;
+
+// Something on the map to collect. `index` is its position in the map's pickups, which
+// is how the server and clients agree on which pickup is which.
+#[derive(Component)]
+pub struct Pickup {
+    pub index: usize,
+    pub kind: PickupKind,
+    respawn_secs: f32,
+    // Running from when it's taken until it comes back.
+    respawn: Option<Timer>,
+    // Asked for and waiting on the answer, so it isn't asked for again every frame.
+    claimed: bool,
+}
+
+impl Pickup {
+    pub fn available(&self) -> bool {
+        self.respawn.is_none()
+    }
+}
+
+// Power-ups running on a player, with the time each has left.
+#[derive(Component, Default, Debug)]
+pub struct PowerUps(HashMap<PickupKind, Timer>);
+
+impl PowerUps {
+    pub fn active(&self, kind: PickupKind) -> bool {
+        self.0.contains_key(&kind)
+    }
+
+    pub fn speed_factor(&self) -> f32 {
+        if self.active(PickupKind::SpeedBoost) {
+            SPEED_BOOST
+        } else {
+            1.
+        }
+    }
+
+    // What's left of `damage` after the shield, if there is one.
+    pub fn damage_taken(&self, damage: i32) -> i32 {
+        if self.active(PickupKind::Shield) {
+            (damage as f32 * SHIELD_FACTOR).round() as i32
+        } else {
+            damage
+        }
+    }
+}
+
+// The local player walked into a pickup that looks available. Online the server decides
+// whether they actually get it, since someone else may have been first.
+#[derive(Event)]
+pub struct PickupTouched {
+    pub index: usize,
+}
+
+// The pickup at `index` has been taken and comes back in `respawn_in` seconds. `local`
+// if it went to the local player.
+#[derive(Event)]
+pub struct PickupTaken {
+    pub index: usize,
+    pub local: bool,
+    pub respawn_in: f32,
+}
+
+#[derive(Component)]
+pub struct PowerUpHud;
+
+pub struct PickupPlugin;
+
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PickupTouched>()
+            .add_event::<PickupTaken>()
+            .add_systems(OnEnter(MapState::Ready), spawn_power_up_hud)
+            .add_systems(
+                Update,
+                (
+                    touch_pickups.run_if(in_state(GameMode::Play).and(player_alive)),
+                    apply_pickups,
+                    respawn_pickups,
+                    tick_power_ups,
+                    clear_power_ups,
+                    update_power_up_hud,
+                )
+                    .chain()
+                    .run_if(in_state(MapState::Ready)),
+            );
+    }
+}
+
+pub fn spawn_pickup(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    index: usize,
+    data: &PickupData,
+) -> Entity {
+    commands
+        .spawn((
+            Pickup {
+                index,
+                kind: data.kind,
+                respawn_secs: data.respawn_secs,
+                respawn: None,
+                claimed: false,
+            },
+            Mesh2d(meshes.add(Circle::new(PICKUP_RADIUS))),
+            MeshMaterial2d(materials.add(data.kind.color())),
+            Transform::from_xyz(data.position.0, data.position.1, 1.0),
+        ))
+        .id()
+}
+
+// Makes a weapon's shots hit harder while a damage boost is running.
+pub fn boost_damage(stats: &mut WeaponStats) {
+    stats.damage = (stats.damage as f32 * DAMAGE_BOOST).round() as i32;
+    if let Some(explosive) = stats.explosive.as_mut() {
+        explosive.damage = (explosive.damage as f32 * DAMAGE_BOOST).round() as i32;
+    }
+}
+
+// The hardest any one bullet or blast can hit, damage boost included.
+pub fn max_hit_damage() -> i32 {
+    WeaponKind::ALL
+        .iter()
+        .map(|kind| {
+            let mut stats = kind.stats();
+            boost_damage(&mut stats);
+            stats
+                .damage
+                .max(stats.explosive.map_or(0, |explosive| explosive.damage))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+pub fn touch_pickups(
+    player_q: Query<&Transform, With<Player>>,
+    mut pickup_q: Query<(&Transform, &mut Pickup)>,
+    mut touched: EventWriter<PickupTouched>,
+) {
+    let Ok(player) = player_q.get_single() else {
+        return;
+    };
+    let player_pos = player.translation.truncate();
+
+    for (transform, mut pickup) in pickup_q.iter_mut() {
+        let reach = PLAYER_RADIUS + PICKUP_RADIUS;
+        if player_pos.distance(transform.translation.truncate()) >= reach {
+            // Walking off and back on asks again, in case the last answer never came.
+            if pickup.claimed {
+                pickup.claimed = false;
+            }
+            continue;
+        }
+        if pickup.available() && !pickup.claimed {
+            pickup.claimed = true;
+            touched.send(PickupTouched {
+                index: pickup.index,
+            });
+        }
+    }
+}
+
+// Offline there's no one else to get there first.
+pub fn grant_pickups_locally(
+    mut touched: EventReader<PickupTouched>,
+    pickup_q: Query<&Pickup>,
+    mut taken: EventWriter<PickupTaken>,
+) {
+    for touch in touched.read() {
+        let Some(pickup) = pickup_q
+            .iter()
+            .find(|pickup| pickup.index == touch.index && pickup.available())
+        else {
+            continue;
+        };
+        taken.send(PickupTaken {
+            index: touch.index,
+            local: true,
+            respawn_in: pickup.respawn_secs,
+        });
+    }
+}
+
+pub fn apply_pickups(
+    mut taken: EventReader<PickupTaken>,
+    mut pickup_q: Query<(&mut Pickup, &mut Visibility)>,
+    mut player_q: Query<(&mut Health, &mut Weapon, &mut Holstered, &mut PowerUps), With<Player>>,
+) {
+    for event in taken.read() {
+        let Some((mut pickup, mut visibility)) = pickup_q
+            .iter_mut()
+            .find(|(pickup, _)| pickup.index == event.index)
+        else {
+            continue;
+        };
+        pickup.respawn = Some(Timer::from_seconds(event.respawn_in, TimerMode::Once));
+        pickup.claimed = false;
+        *visibility = Visibility::Hidden;
+
+        if !event.local {
+            continue;
+        }
+        let Ok((mut health, mut weapon, mut holstered, mut power_ups)) = player_q.get_single_mut()
+        else {
+            continue;
+        };
+        match pickup.kind {
+            PickupKind::Health => {
+                health.current = (health.current + HEALTH_PACK).min(health.max);
+            }
+            PickupKind::Ammo => {
+                weapon.refill();
+                holstered.0.refill();
+            }
+            kind => {
+                power_ups
+                    .0
+                    .insert(kind, Timer::from_seconds(POWER_UP_SECS, TimerMode::Once));
+            }
+        }
+    }
+}
+
+pub fn respawn_pickups(mut pickup_q: Query<(&mut Pickup, &mut Visibility)>, time: Res<Time>) {
+    for (mut pickup, mut visibility) in pickup_q.iter_mut() {
+        let Some(respawn) = pickup.respawn.as_mut() else {
+            continue;
+        };
+        respawn.tick(time.delta());
+        if respawn.finished() {
+            pickup.respawn = None;
+            *visibility = Visibility::Inherited;
+        }
+    }
+}
+
+pub fn tick_power_ups(mut power_ups_q: Query<&mut PowerUps>, time: Res<Time>) {
+    for mut power_ups in power_ups_q.iter_mut() {
+        if power_ups.0.is_empty() {
+            continue;
+        }
+        power_ups
+            .0
+            .retain(|_, timer| !timer.tick(time.delta()).finished());
+    }
+}
+
+// Power-ups don't outlive the player who picked them up.
+pub fn clear_power_ups(
+    mut respawns: EventReader<Respawned>,
+    mut power_ups_q: Query<&mut PowerUps, With<Player>>,
+) {
+    for _ in respawns.read() {
+        for mut power_ups in power_ups_q.iter_mut() {
+            power_ups.0.clear();
+        }
+    }
+}
+
+fn spawn_power_up_hud(mut commands: Commands) {
+    commands.spawn((
+        PowerUpHud,
+        Text::new(""),
+        TextFont {
+            font_size: 24.,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            right: Val::Px(20.),
+            ..default()
+        },
+    ));
+}
+
+fn update_power_up_hud(
+    power_ups_q: Query<&PowerUps, With<Player>>,
+    mut hud_q: Query<&mut Text, With<PowerUpHud>>,
+) {
+    let Ok(power_ups) = power_ups_q.get_single() else {
+        return;
+    };
+    let mut lines: Vec<String> = power_ups
+        .0
+        .iter()
+        .map(|(kind, timer)| format!("{}  {:.0}s", kind.label(), timer.remaining_secs().ceil()))
+        .collect();
+    // The map doesn't keep an order, so keep the lines from jumping around.
+    lines.sort();
+    for mut text in hud_q.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
@@ -1,10 +1,15 @@
 use bevy::{math::*, prelude::*};
 use serde::*;
-use tungstenite::http::header::AGE;
 
 use crate::{
-    colliders::colliders_o::{self, ColliderType},
-    game::{MAP_HEIGHT, MAP_WIDTH},
+    bullets::{Bullet, FiredBy, FriendlyFire, Velocity},
+    colliders::colliders_o::{self, ColliderType, CollisionStarted},
+    death::{Dead, SpawnProtection},
+    health::{spawn_health_bar, Health},
+    map::MapBounds,
+    movement::{HitStun, Momentum, MovementState, Stamina, MAX_STAMINA, SPRINT_FACTOR, STUN_SLOW},
+    pickups::PowerUps,
+    weapons::{barrel_mesh, BarrelShape, Holstered, Loadout, Weapon, WeaponKind},
 };
 
 // #[derive(Serialize, Deserialize, Debug, Resource, Clone)]
@@ -15,93 +20,9 @@ use crate::{
 //         self.0
 //     }
 // }
-#[derive(Component)]
-#[allow(warnings)]
-pub struct PlayerHealthBar {
-    pub current_health: f32,
-}
-
-pub fn update_player_health(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    mut queries: ParamSet<(
-        Query<Entity, With<PlayerHealthBar>>,
-        Query<&Player>,
-        Query<Entity, With<Player>>,
-    )>,
-) {
-    let binding = queries.p1();
-    let player_data = binding.single();
-    let player_health = player_data.current_health;
-    let player = queries.p2().single();
-
-    let entity = queries.p0().single_mut();
-    match player_health {
-        800 => {
-            commands.entity(entity).despawn();
-
-            commands
-                .spawn((
-                    PlayerHealthBar {
-                        current_health: 800.0,
-                    },
-                    Mesh2d(meshes.add(Rectangle::new(104., 25.))),
-                    MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                    Transform::from_xyz(0., -110., 5.0),
-                ))
-                .set_parent(player);
-        }
-        600 => {
-            commands.entity(entity).despawn();
-
-            commands
-                .spawn((
-                    PlayerHealthBar {
-                        current_health: 600.0,
-                    },
-                    Mesh2d(meshes.add(Rectangle::new(78., 25.))),
-                    MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                    Transform::from_xyz(0., -110., 5.0),
-                ))
-                .set_parent(player);
-        }
-        400 => {
-            commands.entity(entity).despawn();
-
-            commands
-                .spawn((
-                    PlayerHealthBar {
-                        current_health: 400.0,
-                    },
-                    Mesh2d(meshes.add(Rectangle::new(52., 25.))),
-                    MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                    Transform::from_xyz(0., -110., 5.0),
-                ))
-                .set_parent(player);
-        }
-        200 => {
-            commands.entity(entity).despawn();
-
-            commands
-                .spawn((
-                    PlayerHealthBar {
-                        current_health: 200.0,
-                    },
-                    Mesh2d(meshes.add(Rectangle::new(26., 25.))),
-                    MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                    Transform::from_xyz(0., -110., 5.0),
-                ))
-                .set_parent(player);
-        }
-        0 => {
-            commands.entity(entity).despawn();
-        }
-        _ => {
-            eprintln!("Unhandled player health value: {}", player_health);
-        }
-    }
-}
+// Every player body, local, remote or AI, is a circle this size.
+pub const PLAYER_RADIUS: f32 = 70.0;
+pub const PLAYER_MAX_HEALTH: i32 = 1000;
 
 #[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Player {
@@ -109,6 +30,24 @@ pub struct Player {
     pub position: (f32, f32),
     pub angle: f32,
     pub just_shot: bool,
+    // Which weapon `just_shot` came from, so others can replay the whole shot.
+    pub weapon: WeaponKind,
+    // What that shot's spread was rolled from, and the angle it went out at.
+    pub shot_seed: u64,
+    pub shot_angle: f32,
+    // Whether that shot had a damage boost behind it.
+    pub damage_boost: bool,
+    // For the trail others see behind this player.
+    pub sprinting: bool,
+    pub dashing: bool,
+    // How fast and which way they're going, so others can keep them moving between
+    // updates.
+    pub velocity: (f32, f32),
+    pub heading: (f32, f32),
+    // So others play the death animation and see the spawn protection blink.
+    pub dead: bool,
+    pub protected: bool,
+    // What gets sent over the network. In game the entity's `Health` is what counts.
     pub current_health: i32,
 }
 
@@ -118,15 +57,27 @@ impl Player {
         meshes: &mut ResMut<Assets<Mesh>>,
         materials: &mut ResMut<Assets<ColorMaterial>>,
         player: Player,
+        loadout: Loadout,
     ) {
         let clone = player.clone();
 
         let playe: Entity = commands
             .spawn((
+                Weapon::new(loadout.primary),
+                Holstered(Weapon::new(loadout.secondary)),
+                loadout,
+                PowerUps::default(),
+                Stamina::new(MAX_STAMINA),
+                Momentum::default(),
+                MovementState::default(),
                 player,
+                Health {
+                    current: clone.current_health,
+                    max: PLAYER_MAX_HEALTH,
+                },
                 colliders_o::CircleCollider {
                     collider_type: ColliderType::Player,
-                    radius: 70.0,
+                    radius: PLAYER_RADIUS,
                 },
                 Mesh2d(meshes.add(Circle::new(70.))),
                 MeshMaterial2d(materials.add(Color::hsla(223., 0.54, 0.34, 1.))),
@@ -134,58 +85,116 @@ impl Player {
             ))
             .id();
 
-        commands.spawn(Camera2d).set_parent(playe);
+        commands.spawn((
+            Camera2d,
+            Transform::from_xyz(clone.position.0, clone.position.1, 0.0),
+        ));
 
         commands
             .spawn((
                 Barrel,
-                Mesh2d(meshes.add(Rectangle::new(80., 60.))),
+                BarrelShape(loadout.primary),
+                Mesh2d(meshes.add(barrel_mesh(loadout.primary))),
                 MeshMaterial2d(materials.add(Color::hsla(223., 0.54, 0.34, 1.))),
                 Transform::from_xyz(70.0, 0.0, 4.0),
             ))
             .set_parent(playe);
 
-        commands
-            .spawn((
-                PlayerHealthBar {
-                    current_health: 1000.0,
-                },
-                Mesh2d(meshes.add(Rectangle::new(130., 25.))),
-                MeshMaterial2d(materials.add(Color::srgb(0., 100., 0.))),
-                Transform::from_xyz(0., -110., 5.0),
-            ))
-            .set_parent(playe);
-
-        commands
-            .spawn((
-                Mesh2d(meshes.add(Rectangle::new(135., 30.))),
-                MeshMaterial2d(materials.add(Color::hsl(159., 0.92, 0.10))),
-                Transform::from_xyz(0., -110., 4.0),
-            ))
-            .set_parent(playe);
+        spawn_health_bar(commands, meshes, materials, playe);
     }
 }
 
 #[derive(Component)]
 pub struct Barrel;
 
+// The local player while bullets can still hurt it.
+type TargetQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Player,
+        &'static mut Health,
+        &'static PowerUps,
+        &'static mut Momentum,
+    ),
+    (Without<Dead>, Without<SpawnProtection>),
+>;
+
+pub fn take_bullet_damage(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionStarted>,
+    bullet_q: Query<(&Bullet, &FiredBy, &Velocity)>,
+    mut player_q: TargetQuery,
+    friendly_fire: Res<FriendlyFire>,
+) {
+    for collision in collisions.read() {
+        let Ok((bullet, fired_by, velocity)) = bullet_q.get(collision.a) else {
+            continue;
+        };
+        let Ok((entity, player, mut health, power_ups, mut momentum)) =
+            player_q.get_mut(collision.b)
+        else {
+            continue;
+        };
+        if health.is_dead() || !fired_by.can_hurt(&player.id, &friendly_fire) {
+            continue;
+        }
+        health.current -= power_ups.damage_taken(bullet.current_damage());
+        // Thrown through the movement, so the block collisions keep the player out of walls.
+        momentum.shove(
+            Vec2::new(velocity.x, velocity.y),
+            bullet.current_knockback(),
+        );
+        if bullet.stun_secs > 0. {
+            commands
+                .entity(entity)
+                .insert(HitStun::new(bullet.stun_secs));
+        }
+        if health.is_dead() {
+            println!("Player: {} was killed by {}", player.id, fired_by.owner);
+            commands
+                .entity(entity)
+                .insert(Dead::new(Some(fired_by.entity)));
+        }
+    }
+}
+
+// The local player, with everything that changes how fast it moves.
+type MoverQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Transform,
+        &'static PowerUps,
+        &'static mut Stamina,
+        &'static mut Momentum,
+        Has<HitStun>,
+    ),
+    With<Player>,
+>;
+
 pub fn player_movement(
-    mut query: Query<(&mut Transform, &Player)>,
+    mut query: MoverQuery,
     //mut network_pos: ResMut<NetworkPosition>,
     keyboard: Res<ButtonInput<KeyCode>>,
     time: Res<Time>,
+    bounds: Res<MapBounds>,
 ) {
-    for (mut transform, _player) in query.iter_mut() {
-        let mut direction = Vec3::ZERO;
-        let speed = 800.0;
+    for (mut transform, power_ups, mut stamina, mut momentum, stunned) in query.iter_mut() {
+        let mut direction = Vec2::ZERO;
+        let mut speed_factor = power_ups.speed_factor();
+        if stunned {
+            speed_factor *= STUN_SLOW;
+        }
 
         // Get current position
         let current_x = transform.translation.x;
         let current_y = transform.translation.y;
 
-        // Calculate boundaries (accounting for half the screen size)
-        let max_x = (MAP_WIDTH / 2.0) - 60.0;
-        let max_y = (MAP_HEIGHT / 2.0) - 60.0;
+        // Calculate boundaries (accounting for the boundary walls and the player's radius)
+        let max_x = bounds.playable_half_extents().x - 70.0;
+        let max_y = bounds.playable_half_extents().y - 70.0;
 
         // Check movement input
         if keyboard.pressed(KeyCode::KeyW) && current_y < max_y {
@@ -201,13 +210,24 @@ pub fn player_movement(
             direction.x += 1.0;
         }
 
-        if direction != Vec3::ZERO {
-            direction = direction.normalize();
-            let new_pos = transform.translation + direction * speed * time.delta_secs();
+        // Sprinting only drains stamina while actually moving.
+        let wants_sprint = direction != Vec2::ZERO
+            && keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        if stamina.sprint(wants_sprint, time.delta_secs()) {
+            speed_factor *= SPRINT_FACTOR;
+        }
+
+        let new_pos = transform.translation.truncate()
+            + momentum.step(direction, speed_factor, time.delta_secs());
 
-            // Clamp the position to screen bounds
-            transform.translation.x = new_pos.x.clamp(-max_x, max_x);
-            transform.translation.y = new_pos.y.clamp(-max_y, max_y);
+        // Clamp the position to screen bounds, and stop dead against them
+        transform.translation.x = new_pos.x.clamp(-max_x, max_x);
+        transform.translation.y = new_pos.y.clamp(-max_y, max_y);
+        if transform.translation.x != new_pos.x {
+            momentum.velocity.x = 0.;
+        }
+        if transform.translation.y != new_pos.y {
+            momentum.velocity.y = 0.;
         }
 
         // Update the network position
@@ -215,17 +235,48 @@ pub fn player_movement(
     }
 }
 
+// The game camera, kept apart from the player so both transforms can be borrowed.
+type CameraQuery<'w, 's> = Query<
+    'w,
+    's,
+    (&'static mut Transform, &'static OrthographicProjection),
+    (With<Camera2d>, Without<Player>),
+>;
+
+pub fn follow_player_camera(
+    player_q: Query<(&Transform, Option<&Dead>), With<Player>>,
+    target_q: Query<&Transform, Without<Camera2d>>,
+    mut camera_q: CameraQuery,
+    bounds: Res<MapBounds>,
+) {
+    let Ok((player, dead)) = player_q.get_single() else {
+        return;
+    };
+    let Ok((mut camera, projection)) = camera_q.get_single_mut() else {
+        return;
+    };
+    // While dead, watch whoever did it for as long as they're around.
+    let target = dead
+        .and_then(|dead| dead.killer)
+        .and_then(|killer| target_q.get(killer).ok())
+        .unwrap_or(player);
+
+    // Keep the whole view inside the map; if the view is bigger than the map, center it.
+    let view_half = projection.area.half_size();
+    let limit = (bounds.playable_half_extents() - view_half).max(Vec2::ZERO);
+    camera.translation.x = target.translation.x.clamp(-limit.x, limit.x);
+    camera.translation.y = target.translation.y.clamp(-limit.y, limit.y);
+}
+
 pub fn rotate_barrel(
     windows: Query<&Window>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
-    mut queries: ParamSet<(
-        Query<&Transform, With<Player>>,
-        Query<&mut Transform, With<Barrel>>,
-        Query<&mut Player>,
-    )>,
+    mut player_q: Query<(&Transform, &mut Player)>,
+    mut barrel_q: Query<&mut Transform, (With<Barrel>, Without<Player>)>,
 ) {
     // Get player position first and store it
-    let player_pos = queries.p0().single().translation.truncate();
+    let (player_transform, mut player) = player_q.single_mut();
+    let player_pos = player_transform.translation.truncate();
 
     // Get the window and cursor position
     let window = windows.single();
@@ -241,13 +292,13 @@ pub fn rotate_barrel(
     let angle = 0.;
     // Convert cursor position to world coordinates
     if let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position) {
-        for mut barrel_transform in queries.p1().iter_mut() {
+        for mut barrel_transform in barrel_q.iter_mut() {
             let direction = world_position - player_pos;
             let angle = direction.y.atan2(direction.x);
 
             barrel_transform.rotation = Quat::from_rotation_z(angle);
             barrel_transform.translation = Vec3::new(angle.cos() * 70., angle.sin() * 70., 0.0);
         }
-        queries.p2().single_mut().angle = angle;
+        player.angle = angle;
     }
 }
@@ -1,60 +1,160 @@
-mod audio;
-mod block;
-mod bullets;
-mod colliders;
-mod enemy;
-mod game;
-mod player;
+use b::{block, colliders, death, map, movement, net, pickups, player, weapons};
 
+use bevy::math::Vec2;
+use block::Block;
+use colliders::colliders_o::{circle_circle_push, circle_polygon_push};
+use death::RESPAWN_SECONDS;
+use map::{MapData, MapSource};
+use movement::Momentum;
+use net::{ClientMessage, MessageBuffer, ServerMessage, Welcome};
+use pickups::{max_hit_damage, PICKUP_RADIUS};
 use player::*;
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use weapons::{Loadout, WeaponKind};
 
 type Players = Arc<Mutex<HashMap<String, Player>>>;
 type ClientStreams = Arc<Mutex<HashMap<String, TcpStream>>>;
+// Health of each destructible wall, by its index in the map.
+type BlockHealth = Arc<Mutex<HashMap<usize, i32>>>;
+// When each pickup that's been taken comes back, by its index in the map.
+type PickupRespawns = Arc<Mutex<HashMap<usize, Instant>>>;
+
+// Overlap the server lets slide before correcting anyone, since clients already keep
+// themselves out of each other and only disagree by rounding.
+const BODY_SLOP: f32 = 1.0;
+// Extra reach allowed when claiming a pickup, since the claim can arrive a little after
+// the position update that put the player there.
+const PICKUP_SLACK: f32 = 100.0;
+// How much sooner than the respawn delay a respawn is still honored, since the server only
+// hears about a death once the update carrying it arrives.
+const RESPAWN_SLACK: Duration = Duration::from_millis(500);
 
 fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let source = match MapSource::from_args(&args) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let map = match source.load() {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Failed to load map {}: {}", source.path(), e);
+            std::process::exit(1);
+        }
+    };
+    println!("Serving map {} ({} walls)", map.name, map.walls.len());
+
     let listener = TcpListener::bind("0.0.0.0:8080")?;
     let players: Players = Arc::new(Mutex::new(HashMap::new()));
     let client_streams: ClientStreams = Arc::new(Mutex::new(HashMap::new()));
+    let block_health: BlockHealth = Arc::new(Mutex::new(
+        map.walls
+            .iter()
+            .enumerate()
+            .filter_map(|(index, wall)| Some((index, wall.health?)))
+            .collect(),
+    ));
+    let pickup_respawns: PickupRespawns = Arc::new(Mutex::new(HashMap::new()));
+
+    let map = Arc::new(map);
 
     println!("Server listening");
 
-    for stream in listener.incoming() {
-        if let Ok(mut stream) = stream {
-            let mut buffer = [0; 512];
-            let n = stream.read(&mut buffer)?;
-            let iden = String::from_utf8_lossy(&buffer[..n]).to_string();
-
-            println!("Player {} connected", iden);
-
-            let players_clone = Arc::clone(&players);
-            let client_streams_clone = Arc::clone(&client_streams);
-
-            // Add the new player and their stream to the shared data structures
-            players_clone.lock().unwrap().insert(
-                iden.clone(),
-                Player {
-                    id: iden.clone(),
-                    position: (0., 0.),
-                    angle: 0.,
-                    just_shot: false,
-                    current_health: 1000,
-                },
-            );
-
-            client_streams_clone
-                .lock()
-                .unwrap()
-                .insert(iden.clone(), stream.try_clone().unwrap());
-
-            // Spawn a thread to handle the client
-            thread::spawn(move || handle_client(stream, players_clone, client_streams_clone, iden));
+    let mut next_spawn = 0;
+    for mut stream in listener.incoming().flatten() {
+        let mut buffer = [0; 512];
+        let n = stream.read(&mut buffer)?;
+        let iden = String::from_utf8_lossy(&buffer[..n]).to_string();
+
+        println!("Player {} connected", iden);
+
+        // Spawn points are handed out in turn, so players joining together don't all
+        // land in the same place.
+        let position = if map.spawn_points.is_empty() {
+            (0., 0.)
+        } else {
+            let position = map.spawn_points[next_spawn];
+            next_spawn = (next_spawn + 1) % map.spawn_points.len();
+            position
+        };
+
+        // Tell the client which map to build and where it starts before it starts
+        // sending updates, and what's been shot up so far.
+        let mut sync = net::encode(&Welcome {
+            map: source.clone(),
+            spawn: position,
+        });
+        for (&index, &health) in block_health.lock().unwrap().iter() {
+            sync.extend(net::encode(&ServerMessage::BlockHealth { index, health }));
+        }
+        let now = Instant::now();
+        for (&index, &back) in pickup_respawns.lock().unwrap().iter() {
+            if back > now {
+                sync.extend(net::encode(&ServerMessage::PickupTaken {
+                    index,
+                    by: String::new(),
+                    respawn_in: (back - now).as_secs_f32(),
+                }));
+            }
+        }
+        if let Err(e) = stream.write_all(&sync) {
+            eprintln!("Failed to send map to {}: {}", iden, e);
+            continue;
         }
+
+        let players_clone = Arc::clone(&players);
+        let client_streams_clone = Arc::clone(&client_streams);
+        let block_health_clone = Arc::clone(&block_health);
+        let pickup_respawns_clone = Arc::clone(&pickup_respawns);
+        let map_clone = Arc::clone(&map);
+
+        // Add the new player and their stream to the shared data structures
+        players_clone.lock().unwrap().insert(
+            iden.clone(),
+            Player {
+                id: iden.clone(),
+                position,
+                angle: 0.,
+                just_shot: false,
+                weapon: WeaponKind::default(),
+                shot_seed: 0,
+                shot_angle: 0.,
+                damage_boost: false,
+                sprinting: false,
+                dashing: false,
+                velocity: (0., 0.),
+                heading: (0., 0.),
+                dead: false,
+                protected: false,
+                current_health: PLAYER_MAX_HEALTH,
+            },
+        );
+
+        client_streams_clone
+            .lock()
+            .unwrap()
+            .insert(iden.clone(), stream.try_clone().unwrap());
+
+        // Spawn a thread to handle the client
+        thread::spawn(move || {
+            handle_client(
+                stream,
+                players_clone,
+                client_streams_clone,
+                block_health_clone,
+                pickup_respawns_clone,
+                map_clone,
+                iden,
+            )
+        });
     }
 
     Ok(())
@@ -64,9 +164,17 @@ fn handle_client(
     mut player_stream: TcpStream,
     players: Players,
     client_streams: ClientStreams,
+    block_health: BlockHealth,
+    pickup_respawns: PickupRespawns,
+    map: Arc<MapData>,
     player_id: String,
 ) {
     let mut buffer = [0; 512];
+    let mut messages = MessageBuffer::default();
+    let mut loadout = Loadout::default();
+    // When this player's updates first showed them dead, so they can't come back early.
+    let mut died_at: Option<Instant> = None;
+    let blocks: Vec<Block> = map.blocks().collect();
 
     loop {
         match player_stream.read(&mut buffer) {
@@ -75,34 +183,170 @@ fn handle_client(
                 break;
             }
             Ok(bytes_read) => {
-                if let Ok(player_update) = bincode::deserialize::<Player>(&buffer[..bytes_read]) {
-                    println!(
-                        "Player {} moved to: ({}, {}), angle: {}, health: {}",
-                        player_update.id,
-                        player_update.position.0,
-                        player_update.position.1,
-                        player_update.angle,
-                        player_update.current_health,
-                    );
-
-                    players
-                        .lock()
-                        .unwrap()
-                        .insert(player_update.id.clone(), player_update.clone());
-
-                    let client_streams_guard = client_streams.lock().unwrap();
-                    for (id, stream) in client_streams_guard.iter() {
-                        if *id != player_id {
-                            if let Ok(serialized_data) = bincode::serialize(&player_update) {
-                                if let Err(e) =
-                                    stream.try_clone().unwrap().write_all(&serialized_data)
-                                {
-                                    eprintln!("Failed to send update to client {}: {}", id, e);
-                                }
+                messages.extend(&buffer[..bytes_read]);
+                let dropped = messages.dropped;
+                for message in messages.drain::<ClientMessage>() {
+                    match message {
+                        ClientMessage::Update(mut player_update) => {
+                            println!(
+                                "Player {} moved to: ({}, {}), angle: {}, health: {}",
+                                player_update.id,
+                                player_update.position.0,
+                                player_update.position.1,
+                                player_update.angle,
+                                player_update.current_health,
+                            );
+
+                            // Nobody gets to fire a gun they didn't bring.
+                            if !loadout.carries(player_update.weapon) {
+                                player_update.weapon = loadout.primary;
+                            }
+                            // Everyone else keeps this player moving on it between updates,
+                            // so it can't be faster than running or being thrown allows.
+                            let velocity = Vec2::from(player_update.velocity)
+                                .clamp_length_max(Momentum::default().speed_limit());
+                            player_update.velocity = velocity.into();
+                            if player_update.current_health > 0 {
+                                died_at = None;
+                            } else if died_at.is_none() {
+                                died_at = Some(Instant::now());
+                            }
+
+                            let mut players = players.lock().unwrap();
+                            let broken = block_health.lock().unwrap();
+                            let correction =
+                                resolve_body_collisions(&player_update, &players, &blocks, &broken);
+                            drop(broken);
+                            if let Some(position) = correction {
+                                player_update.position = position;
+                                send_to(
+                                    &client_streams,
+                                    &player_id,
+                                    &ServerMessage::Correction { position },
+                                );
+                            }
+                            players.insert(player_update.id.clone(), player_update.clone());
+                            drop(players);
+
+                            broadcast(
+                                &client_streams,
+                                &ServerMessage::Update(player_update),
+                                Some(&player_id),
+                            );
+                        }
+                        ClientMessage::Respawn => {
+                            // Respawning heals and moves you, so only the dead get to, and
+                            // only once they've waited it out.
+                            let waited = died_at.is_some_and(|died_at| {
+                                died_at.elapsed() + RESPAWN_SLACK
+                                    >= Duration::from_secs_f32(RESPAWN_SECONDS)
+                            });
+                            let mut players = players.lock().unwrap();
+                            let dead = players
+                                .get(&player_id)
+                                .is_some_and(|player| player.current_health <= 0);
+                            if !dead || !waited {
+                                continue;
+                            }
+                            // Only the living are worth avoiding.
+                            let enemies: Vec<Vec2> = players
+                                .values()
+                                .filter(|other| other.id != player_id && other.current_health > 0)
+                                .map(|other| Vec2::new(other.position.0, other.position.1))
+                                .collect();
+                            let position = map.pick_spawn(&enemies);
+                            if let Some(player) = players.get_mut(&player_id) {
+                                player.position = position;
+                                player.current_health = PLAYER_MAX_HEALTH;
+                            }
+                            drop(players);
+                            died_at = None;
+
+                            println!(
+                                "Player {} respawned at ({}, {})",
+                                player_id, position.0, position.1
+                            );
+                            send_to(
+                                &client_streams,
+                                &player_id,
+                                &ServerMessage::Respawn { position },
+                            );
+                        }
+                        ClientMessage::Loadout(choice) => {
+                            if choice.is_valid() {
+                                println!(
+                                    "Player {} picked {:?} and {:?}",
+                                    player_id, choice.primary, choice.secondary
+                                );
+                                loadout = choice;
+                            }
+                        }
+                        ClientMessage::ClaimPickup { index } => {
+                            let Some(pickup) = map.pickups.get(index) else {
+                                continue;
+                            };
+                            // Only someone alive and actually standing on it can have it.
+                            let reach = PLAYER_RADIUS + PICKUP_RADIUS + PICKUP_SLACK;
+                            let position = players
+                                .lock()
+                                .unwrap()
+                                .get(&player_id)
+                                .filter(|player| player.current_health > 0)
+                                .map(|player| Vec2::from(player.position));
+                            if position.is_none_or(|position| {
+                                position.distance(Vec2::from(pickup.position)) > reach
+                            }) {
+                                continue;
+                            }
+
+                            let mut pickup_respawns = pickup_respawns.lock().unwrap();
+                            let now = Instant::now();
+                            if pickup_respawns.get(&index).is_some_and(|&back| back > now) {
+                                continue;
+                            }
+                            pickup_respawns
+                                .insert(index, now + Duration::from_secs_f32(pickup.respawn_secs));
+                            drop(pickup_respawns);
+
+                            println!("Player {} picked up {:?}", player_id, pickup.kind);
+                            broadcast(
+                                &client_streams,
+                                &ServerMessage::PickupTaken {
+                                    index,
+                                    by: player_id.clone(),
+                                    respawn_in: pickup.respawn_secs,
+                                },
+                                None,
+                            );
+                        }
+                        ClientMessage::DamageBlock { index, damage } => {
+                            // Nothing heals a wall, and nothing hits harder than the
+                            // strongest boosted shot or blast.
+                            if damage <= 0 || damage > max_hit_damage() {
+                                continue;
+                            }
+                            let mut block_health = block_health.lock().unwrap();
+                            // Unknown or already destroyed blocks can't take more damage.
+                            let Some(health) = block_health.get_mut(&index).filter(|h| **h > 0)
+                            else {
+                                continue;
+                            };
+                            *health -= damage;
+                            let health = *health;
+                            drop(block_health);
+
+                            if health <= 0 {
+                                println!("Player {} destroyed block {}", player_id, index);
                             }
+                            broadcast(
+                                &client_streams,
+                                &ServerMessage::BlockHealth { index, health },
+                                None,
+                            );
                         }
                     }
-                } else {
+                }
+                if messages.dropped > dropped {
                     eprintln!("Could not deserialize player info");
                 }
             }
@@ -116,3 +360,57 @@ fn handle_client(
     players.lock().unwrap().remove(&player_id);
     client_streams.lock().unwrap().remove(&player_id);
 }
+
+// The server has the final say on where bodies end up: a player that moved into someone
+// else is pushed back out, and then out of any wall that push put it in. `block_health`
+// is there to skip walls that have been shot down. Returns the corrected position, if it
+// needed one.
+fn resolve_body_collisions(
+    mover: &Player,
+    players: &HashMap<String, Player>,
+    blocks: &[Block],
+    block_health: &HashMap<usize, i32>,
+) -> Option<(f32, f32)> {
+    let reported = Vec2::new(mover.position.0, mover.position.1);
+    let mut position = reported;
+    for other in players.values().filter(|other| other.id != mover.id) {
+        let other_pos = Vec2::new(other.position.0, other.position.1);
+        if let Some(push) = circle_circle_push(position, PLAYER_RADIUS, other_pos, PLAYER_RADIUS) {
+            position += push;
+        }
+    }
+    if position.distance(reported) <= BODY_SLOP {
+        return None;
+    }
+
+    for (index, block) in blocks.iter().enumerate() {
+        if block_health.get(&index).is_some_and(|&health| health <= 0) {
+            continue;
+        }
+        if let Some(push) = circle_polygon_push(position, PLAYER_RADIUS, &block.points) {
+            position += push;
+        }
+    }
+    Some((position.x, position.y))
+}
+
+fn send_to(client_streams: &ClientStreams, id: &str, message: &ServerMessage) {
+    let client_streams_guard = client_streams.lock().unwrap();
+    if let Some(stream) = client_streams_guard.get(id) {
+        if let Err(e) = stream.try_clone().unwrap().write_all(&net::encode(message)) {
+            eprintln!("Failed to send update to client {}: {}", id, e);
+        }
+    }
+}
+
+fn broadcast(client_streams: &ClientStreams, message: &ServerMessage, except: Option<&str>) {
+    let serialized_data = net::encode(message);
+    let client_streams_guard = client_streams.lock().unwrap();
+    for (id, stream) in client_streams_guard.iter() {
+        if Some(id.as_str()) != except {
+            if let Err(e) = stream.try_clone().unwrap().write_all(&serialized_data) {
+                eprintln!("Failed to send update to client {}: {}", id, e);
+            }
+        }
+    }
+}
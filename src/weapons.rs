@@ -0,0 +1,746 @@
+use bevy::{input::mouse::MouseWheel, prelude::*};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bullets::{spawn_bullet, FiredBy},
+    death::{player_alive, Dead, Respawned},
+    editor::GameMode,
+    explosions::ExplosiveStats,
+    map::MapState,
+    player::Player,
+};
+
+// Time after switching before the new weapon can fire.
+const DRAW_SECS: f32 = 0.35;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WeaponKind {
+    #[default]
+    Pistol,
+    Rifle,
+    Shotgun,
+    Sniper,
+    Launcher,
+    Grenades,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeaponStats {
+    pub name: &'static str,
+    // Seconds between shots.
+    pub fire_interval: f32,
+    pub magazine: u32,
+    pub reload_secs: f32,
+    pub bullet_speed: f32,
+    pub damage: i32,
+    // Damage starts dropping past `falloff_start` and the bullet is gone at `range`.
+    pub falloff_start: f32,
+    pub range: f32,
+    // Width of the cone shots land in, in radians.
+    pub spread: f32,
+    // Bullets per shot.
+    pub projectiles: u32,
+    // Times a bullet glances off a block before it stops.
+    pub ricochets: u32,
+    // Thickest block a bullet can punch through, losing some damage each time.
+    pub penetration: f32,
+    // How far a hit throws the victim back, and how long it slows them down after.
+    pub knockback: f32,
+    pub stun_secs: f32,
+    // Keeps firing while the trigger is held, instead of once per click.
+    pub automatic: bool,
+    // Size of the barrel mesh, so others can tell what's being carried.
+    pub barrel: Vec2,
+    // Projectiles that blow up instead of only hitting what they touch.
+    pub explosive: Option<ExplosiveStats>,
+}
+
+impl WeaponKind {
+    pub const ALL: [WeaponKind; 6] = [
+        WeaponKind::Pistol,
+        WeaponKind::Rifle,
+        WeaponKind::Shotgun,
+        WeaponKind::Sniper,
+        WeaponKind::Launcher,
+        WeaponKind::Grenades,
+    ];
+
+    pub const PRIMARIES: [WeaponKind; 4] = [
+        WeaponKind::Rifle,
+        WeaponKind::Shotgun,
+        WeaponKind::Sniper,
+        WeaponKind::Launcher,
+    ];
+
+    pub const SECONDARIES: [WeaponKind; 2] = [WeaponKind::Pistol, WeaponKind::Grenades];
+
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg.to_lowercase().as_str() {
+            "pistol" => Some(WeaponKind::Pistol),
+            "rifle" => Some(WeaponKind::Rifle),
+            "shotgun" => Some(WeaponKind::Shotgun),
+            "sniper" => Some(WeaponKind::Sniper),
+            "launcher" => Some(WeaponKind::Launcher),
+            "grenades" => Some(WeaponKind::Grenades),
+            _ => None,
+        }
+    }
+
+    pub fn is_primary(self) -> bool {
+        WeaponKind::PRIMARIES.contains(&self)
+    }
+
+    pub fn stats(self) -> WeaponStats {
+        match self {
+            WeaponKind::Pistol => WeaponStats {
+                name: "Pistol",
+                fire_interval: 0.25,
+                magazine: 12,
+                reload_secs: 1.2,
+                bullet_speed: 3500.,
+                damage: 200,
+                falloff_start: 1500.,
+                range: 4000.,
+                spread: 0.03,
+                projectiles: 1,
+                ricochets: 0,
+                penetration: 0.,
+                knockback: 20.,
+                stun_secs: 0.,
+                automatic: false,
+                explosive: None,
+                barrel: Vec2::new(80., 60.),
+            },
+            WeaponKind::Rifle => WeaponStats {
+                name: "Rifle",
+                fire_interval: 0.1,
+                magazine: 30,
+                reload_secs: 2.,
+                bullet_speed: 4000.,
+                damage: 120,
+                falloff_start: 2500.,
+                range: 6000.,
+                spread: 0.08,
+                projectiles: 1,
+                ricochets: 0,
+                penetration: 70.,
+                knockback: 12.,
+                stun_secs: 0.,
+                automatic: true,
+                explosive: None,
+                barrel: Vec2::new(120., 44.),
+            },
+            WeaponKind::Shotgun => WeaponStats {
+                name: "Shotgun",
+                fire_interval: 0.8,
+                magazine: 6,
+                reload_secs: 2.5,
+                bullet_speed: 3000.,
+                damage: 80,
+                falloff_start: 600.,
+                range: 1800.,
+                spread: 0.4,
+                projectiles: 8,
+                ricochets: 1,
+                penetration: 0.,
+                knockback: 18.,
+                stun_secs: 0.15,
+                automatic: false,
+                explosive: None,
+                barrel: Vec2::new(100., 70.),
+            },
+            WeaponKind::Sniper => WeaponStats {
+                name: "Sniper",
+                fire_interval: 1.5,
+                magazine: 5,
+                reload_secs: 3.,
+                bullet_speed: 7000.,
+                damage: 600,
+                falloff_start: 8000.,
+                range: 12000.,
+                spread: 0.,
+                projectiles: 1,
+                ricochets: 0,
+                penetration: 150.,
+                knockback: 90.,
+                stun_secs: 0.4,
+                automatic: false,
+                explosive: None,
+                barrel: Vec2::new(160., 34.),
+            },
+            WeaponKind::Launcher => WeaponStats {
+                name: "Launcher",
+                fire_interval: 1.2,
+                magazine: 3,
+                reload_secs: 3.,
+                bullet_speed: 1600.,
+                // Direct hits; the blast does the rest.
+                damage: 100,
+                falloff_start: 5000.,
+                range: 5000.,
+                spread: 0.02,
+                projectiles: 1,
+                ricochets: 0,
+                penetration: 0.,
+                knockback: 40.,
+                stun_secs: 0.,
+                automatic: false,
+                explosive: Some(ExplosiveStats {
+                    radius: 300.,
+                    damage: 500,
+                    knockback: 150.,
+                    stun_secs: 0.6,
+                    fuse_secs: 3.,
+                    drag: 0.,
+                    lobbed: false,
+                }),
+                barrel: Vec2::new(140., 56.),
+            },
+            WeaponKind::Grenades => WeaponStats {
+                name: "Grenades",
+                fire_interval: 1.,
+                magazine: 2,
+                reload_secs: 4.,
+                bullet_speed: 1800.,
+                damage: 0,
+                falloff_start: 3000.,
+                range: 3000.,
+                spread: 0.05,
+                projectiles: 1,
+                ricochets: 0,
+                penetration: 0.,
+                knockback: 0.,
+                stun_secs: 0.,
+                automatic: false,
+                explosive: Some(ExplosiveStats {
+                    radius: 250.,
+                    damage: 400,
+                    knockback: 120.,
+                    stun_secs: 0.5,
+                    fuse_secs: 1.8,
+                    drag: 1.5,
+                    lobbed: true,
+                }),
+                barrel: Vec2::new(50., 50.),
+            },
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    pub stats: WeaponStats,
+    pub ammo: u32,
+    cooldown: f32,
+    reload: Option<Timer>,
+}
+
+impl Weapon {
+    pub fn new(kind: WeaponKind) -> Self {
+        let stats = kind.stats();
+        Weapon {
+            kind,
+            stats,
+            ammo: stats.magazine,
+            cooldown: 0.,
+            reload: None,
+        }
+    }
+
+    // Getting a weapon out takes a moment, and a reload it was put away in has to start over.
+    fn draw(&mut self) {
+        self.cooldown = self.cooldown.max(DRAW_SECS);
+        self.reload = None;
+    }
+
+    // Full magazine straight away, as from an ammo pickup.
+    pub fn refill(&mut self) {
+        self.ammo = self.stats.magazine;
+        self.reload = None;
+    }
+
+    pub fn reloading(&self) -> bool {
+        self.reload.is_some()
+    }
+
+    pub fn start_reload(&mut self) {
+        if !self.reloading() && self.ammo < self.stats.magazine {
+            self.reload = Some(Timer::from_seconds(self.stats.reload_secs, TimerMode::Once));
+        }
+    }
+
+    // Uses up a round if the weapon is ready. Pulling the trigger on an empty magazine
+    // starts a reload instead.
+    pub fn try_fire(&mut self) -> bool {
+        if self.reloading() || self.cooldown > 0. {
+            return false;
+        }
+        if self.ammo == 0 {
+            self.start_reload();
+            return false;
+        }
+        self.ammo -= 1;
+        self.cooldown = self.stats.fire_interval;
+        true
+    }
+
+    fn tick(&mut self, delta: std::time::Duration) {
+        self.cooldown = (self.cooldown - delta.as_secs_f32()).max(0.);
+        if let Some(reload) = self.reload.as_mut() {
+            reload.tick(delta);
+            if reload.finished() {
+                self.ammo = self.stats.magazine;
+                self.reload = None;
+            }
+        }
+    }
+}
+
+// What a player carries: a primary in slot 1 and a secondary in slot 2.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Loadout {
+    pub primary: WeaponKind,
+    pub secondary: WeaponKind,
+}
+
+impl Default for Loadout {
+    fn default() -> Self {
+        Loadout {
+            primary: WeaponKind::Rifle,
+            secondary: WeaponKind::Pistol,
+        }
+    }
+}
+
+impl Loadout {
+    // Reads `--primary` and `--secondary`, keeping the default for whichever is left out.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut loadout = Loadout::default();
+        for (flag, slot) in [
+            ("--primary", &mut loadout.primary),
+            ("--secondary", &mut loadout.secondary),
+        ] {
+            if let Some(i) = args.iter().position(|arg| arg == flag) {
+                let value = args.get(i + 1).map(String::as_str).unwrap_or_default();
+                *slot = WeaponKind::from_arg(value).ok_or_else(|| {
+                    format!(
+                        "{} expects pistol, rifle, shotgun, sniper, launcher or grenades",
+                        flag
+                    )
+                })?;
+            }
+        }
+        if loadout.is_valid() {
+            Ok(loadout)
+        } else {
+            Err("--primary must be a rifle, shotgun, sniper or launcher, --secondary a pistol or grenades".into())
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.primary.is_primary() && !self.secondary.is_primary()
+    }
+
+    pub fn carries(&self, kind: WeaponKind) -> bool {
+        self.primary == kind || self.secondary == kind
+    }
+}
+
+// The loadout the local player gets next time they spawn. It can be changed before the
+// first spawn and while dead.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct LoadoutChoice(pub Loadout);
+
+// Once the map is up the player picks a loadout, and only spawns once they confirm it.
+#[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LoadoutMenu {
+    #[default]
+    Choosing,
+    Done,
+}
+
+// The slot that isn't in hand. Switching swaps it with the player's `Weapon`, so each
+// keeps its own ammo.
+#[derive(Component, Debug)]
+pub struct Holstered(pub Weapon);
+
+// Which weapon a barrel is drawn as. Set it and the mesh follows.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarrelShape(pub WeaponKind);
+
+#[derive(Component)]
+pub struct LoadoutHud;
+
+// The local player fired. Online this is what tells everyone else to replay the shot,
+// with the same angle and the same spread.
+#[derive(Event)]
+pub struct WeaponFired {
+    pub angle: f32,
+    pub seed: u64,
+}
+
+#[derive(Component)]
+pub struct AmmoHud;
+
+pub struct WeaponPlugin;
+
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<WeaponFired>()
+            .init_resource::<LoadoutChoice>()
+            .init_state::<LoadoutMenu>()
+            .add_systems(
+                OnEnter(MapState::Ready),
+                (spawn_ammo_hud, spawn_loadout_hud),
+            )
+            .add_systems(
+                Update,
+                (
+                    tick_weapons,
+                    (reload_on_key, switch_weapon).run_if(player_alive),
+                    choose_loadout.run_if(not(player_alive)),
+                    confirm_loadout.run_if(in_state(LoadoutMenu::Choosing)),
+                    equip_loadout,
+                    update_ammo_hud,
+                    update_loadout_hud,
+                )
+                    .run_if(in_state(MapState::Ready).and(in_state(GameMode::Play))),
+            )
+            .add_systems(PostUpdate, (sync_barrel_shape, reshape_barrels).chain());
+    }
+}
+
+// One pull of the trigger: who fired, from where and which way. The random amount each
+// bullet is turned by within the spread comes from `seed`, so a replayed shot puts its
+// bullets where the shooter's did.
+pub struct Shot {
+    pub fired_by: FiredBy,
+    pub origin: Vec2,
+    pub angle: f32,
+    pub seed: u64,
+}
+
+// Spawns every bullet of one shot.
+pub fn spawn_shot(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    shot: Shot,
+    stats: &WeaponStats,
+) -> Vec<Entity> {
+    let mut rng = StdRng::seed_from_u64(shot.seed);
+    let half_spread = stats.spread / 2.;
+    (0..stats.projectiles)
+        .map(|_| {
+            let angle = shot.angle + rng.gen_range(-half_spread..=half_spread);
+            spawn_bullet(
+                commands,
+                meshes,
+                materials,
+                shot.fired_by.clone(),
+                shot.origin,
+                angle,
+                stats,
+            )
+        })
+        .collect()
+}
+
+pub fn tick_weapons(mut weapon_q: Query<&mut Weapon>, time: Res<Time>) {
+    for mut weapon in weapon_q.iter_mut() {
+        weapon.tick(time.delta());
+    }
+}
+
+pub fn reload_on_key(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut weapon_q: Query<&mut Weapon, With<Player>>,
+) {
+    if keyboard.just_pressed(KeyCode::KeyR) {
+        for mut weapon in weapon_q.iter_mut() {
+            weapon.start_reload();
+        }
+    }
+}
+
+// 1 and 2 pick a slot, the scroll wheel flips between them.
+pub fn switch_weapon(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut scroll: EventReader<MouseWheel>,
+    mut player_q: Query<(&mut Weapon, &mut Holstered, &Loadout), With<Player>>,
+) {
+    let scrolled = scroll.read().any(|event| event.y != 0.);
+    let Ok((mut weapon, mut holstered, loadout)) = player_q.get_single_mut() else {
+        return;
+    };
+
+    let wanted = if keyboard.just_pressed(KeyCode::Digit1) {
+        loadout.primary
+    } else if keyboard.just_pressed(KeyCode::Digit2) {
+        loadout.secondary
+    } else if scrolled {
+        holstered.0.kind
+    } else {
+        return;
+    };
+    if weapon.kind == wanted {
+        return;
+    }
+
+    std::mem::swap(&mut *weapon, &mut holstered.0);
+    weapon.draw();
+}
+
+// Before spawning and while waiting to respawn, 1 to 4 pick the primary to come back
+// with and 5 and 6 the secondary.
+pub fn choose_loadout(keyboard: Res<ButtonInput<KeyCode>>, mut choice: ResMut<LoadoutChoice>) {
+    let primary_keys = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+    ];
+    for (key, primary) in primary_keys.into_iter().zip(WeaponKind::PRIMARIES) {
+        if keyboard.just_pressed(key) && choice.0.primary != primary {
+            choice.0.primary = primary;
+        }
+    }
+    let secondary_keys = [KeyCode::Digit5, KeyCode::Digit6];
+    for (key, secondary) in secondary_keys.into_iter().zip(WeaponKind::SECONDARIES) {
+        if keyboard.just_pressed(key) && choice.0.secondary != secondary {
+            choice.0.secondary = secondary;
+        }
+    }
+}
+
+// Enter spawns the player with whatever is picked.
+pub fn confirm_loadout(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<LoadoutMenu>>,
+) {
+    if keyboard.just_pressed(KeyCode::Enter) {
+        next_state.set(LoadoutMenu::Done);
+    }
+}
+
+// Coming back hands out fresh weapons from the chosen loadout.
+pub fn equip_loadout(
+    mut respawns: EventReader<Respawned>,
+    choice: Res<LoadoutChoice>,
+    mut player_q: Query<(&mut Weapon, &mut Holstered, &mut Loadout), With<Player>>,
+) {
+    for _ in respawns.read() {
+        let Ok((mut weapon, mut holstered, mut loadout)) = player_q.get_single_mut() else {
+            continue;
+        };
+        *loadout = choice.0;
+        *weapon = Weapon::new(loadout.primary);
+        holstered.0 = Weapon::new(loadout.secondary);
+    }
+}
+
+pub fn sync_barrel_shape(
+    player_q: Query<(&Weapon, &Children), With<Player>>,
+    mut barrel_q: Query<&mut BarrelShape>,
+) {
+    for (weapon, children) in player_q.iter() {
+        for &child in children.iter() {
+            if let Ok(mut shape) = barrel_q.get_mut(child) {
+                shape.set_if_neq(BarrelShape(weapon.kind));
+            }
+        }
+    }
+}
+
+pub fn reshape_barrels(
+    mut barrel_q: Query<(&BarrelShape, &mut Mesh2d), Changed<BarrelShape>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (shape, mut mesh) in barrel_q.iter_mut() {
+        mesh.0 = meshes.add(barrel_mesh(shape.0));
+    }
+}
+
+pub fn barrel_mesh(kind: WeaponKind) -> Rectangle {
+    let size = kind.stats().barrel;
+    Rectangle::new(size.x, size.y)
+}
+
+fn spawn_ammo_hud(mut commands: Commands) {
+    commands.spawn((
+        AmmoHud,
+        Text::new(""),
+        TextFont {
+            font_size: 28.,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(16.),
+            right: Val::Px(20.),
+            ..default()
+        },
+    ));
+}
+
+fn update_ammo_hud(
+    weapon_q: Query<&Weapon, With<Player>>,
+    mut hud_q: Query<&mut Text, With<AmmoHud>>,
+) {
+    let Ok(weapon) = weapon_q.get_single() else {
+        return;
+    };
+    for mut text in hud_q.iter_mut() {
+        text.0 = if weapon.reloading() {
+            format!("{}  reloading...", weapon.stats.name)
+        } else {
+            format!(
+                "{}  {}/{}",
+                weapon.stats.name, weapon.ammo, weapon.stats.magazine
+            )
+        };
+    }
+}
+
+fn spawn_loadout_hud(mut commands: Commands) {
+    commands.spawn((
+        LoadoutHud,
+        Text::new(""),
+        TextFont {
+            font_size: 28.,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(16.),
+            left: Val::Px(20.),
+            ..default()
+        },
+        Visibility::Hidden,
+    ));
+}
+
+// Only up before spawning and while dead, since those are the only times the loadout
+// can be changed.
+fn update_loadout_hud(
+    dead_q: Query<(), (With<Player>, With<Dead>)>,
+    menu: Res<State<LoadoutMenu>>,
+    choice: Res<LoadoutChoice>,
+    mut hud_q: Query<(&mut Text, &mut Visibility), With<LoadoutHud>>,
+) {
+    let choosing = *menu.get() == LoadoutMenu::Choosing;
+    let shown = choosing || !dead_q.is_empty();
+    for (mut text, mut visibility) in hud_q.iter_mut() {
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        if shown {
+            let options: Vec<String> = WeaponKind::PRIMARIES
+                .iter()
+                .chain(WeaponKind::SECONDARIES.iter())
+                .enumerate()
+                .map(|(i, kind)| {
+                    let marker = if choice.0.carries(*kind) { ">" } else { " " };
+                    format!("{}[{}] {}", marker, i + 1, kind.stats().name)
+                })
+                .collect();
+            text.0 = format!(
+                "Next loadout: {} + {}\n{}",
+                choice.0.primary.stats().name,
+                choice.0.secondary.stats().name,
+                options.join("   ")
+            );
+            if choosing {
+                text.0.push_str("\n[Enter] spawn");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait(weapon: &mut Weapon, secs: f32) {
+        weapon.tick(Duration::from_secs_f32(secs));
+    }
+
+    // Long enough for the next shot, with room for rounding.
+    fn wait_a_shot(weapon: &mut Weapon) {
+        wait(weapon, weapon.stats.fire_interval + 0.001);
+    }
+
+    #[test]
+    fn fires_once_per_interval() {
+        let mut weapon = Weapon::new(WeaponKind::Rifle);
+        let stats = weapon.stats;
+        assert!(weapon.try_fire());
+        assert!(!weapon.try_fire());
+        wait(&mut weapon, stats.fire_interval / 2.);
+        assert!(!weapon.try_fire());
+        wait_a_shot(&mut weapon);
+        assert!(weapon.try_fire());
+        assert_eq!(weapon.ammo, weapon.stats.magazine - 2);
+    }
+
+    #[test]
+    fn empty_magazine_reloads_instead_of_firing() {
+        let mut weapon = Weapon::new(WeaponKind::Pistol);
+        let stats = weapon.stats;
+        for _ in 0..stats.magazine {
+            assert!(weapon.try_fire());
+            wait_a_shot(&mut weapon);
+        }
+        assert_eq!(weapon.ammo, 0);
+        assert!(!weapon.try_fire());
+        assert!(weapon.reloading());
+
+        wait(&mut weapon, stats.reload_secs / 2.);
+        assert!(!weapon.try_fire());
+        wait(&mut weapon, stats.reload_secs / 2. + 0.001);
+        assert!(!weapon.reloading());
+        assert_eq!(weapon.ammo, stats.magazine);
+        assert!(weapon.try_fire());
+    }
+
+    #[test]
+    fn full_magazine_does_not_reload() {
+        let mut weapon = Weapon::new(WeaponKind::Shotgun);
+        weapon.start_reload();
+        assert!(!weapon.reloading());
+
+        assert!(weapon.try_fire());
+        weapon.start_reload();
+        assert!(weapon.reloading());
+    }
+
+    #[test]
+    fn drawing_cancels_a_reload_and_delays_the_next_shot() {
+        let mut weapon = Weapon::new(WeaponKind::Rifle);
+        assert!(weapon.try_fire());
+        weapon.start_reload();
+        weapon.draw();
+        assert!(!weapon.reloading());
+        assert_eq!(weapon.ammo, weapon.stats.magazine - 1);
+
+        wait(&mut weapon, DRAW_SECS / 2.);
+        assert!(!weapon.try_fire());
+        wait(&mut weapon, DRAW_SECS / 2. + 0.001);
+        assert!(weapon.try_fire());
+    }
+
+    #[test]
+    fn refills_cancel_reloads() {
+        let mut weapon = Weapon::new(WeaponKind::Sniper);
+        assert!(weapon.try_fire());
+        weapon.start_reload();
+        weapon.refill();
+        assert!(!weapon.reloading());
+        assert_eq!(weapon.ammo, weapon.stats.magazine);
+    }
+}